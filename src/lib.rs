@@ -1,35 +1,189 @@
+pub mod lockfile;
 pub mod outputs;
+pub mod packers;
+pub mod processing;
 pub mod sources;
 
 mod util;
 
 use crate::{
+    lockfile::LockError,
     outputs::OutputSpecifier,
-    sources::{SpriteSource, SpriteSpecifier},
+    packers::{MaxRectsHeuristic, PackOptions, PackedRect, Packer},
+    processing::{filter::StateFilter, recolor::HexColor},
+    sources::{
+        Align, Fit, SpriteEntry, SpriteOptions, SpriteSource,
+        fetch::{CacheConfig, FetchContext, SourcesConfig},
+    },
 };
+use anyhow::{Context, bail};
+use futures::{StreamExt, channel::oneshot, stream};
 #[cfg(feature = "bin")]
 use indicatif::ProgressBar;
-use log::warn;
+use log::{info, warn};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::Deserialize;
-use std::collections::HashMap;
-use tiny_skia::{Pixmap, PixmapPaint, Transform};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    iter,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+use tiny_skia::{IntRect, Pixmap, PixmapPaint, Transform};
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct SpritesheetSpecifier {
     pub spritegen: Spritegen,
     pub outputs: Vec<OutputSpecifier>,
-    pub sprites: HashMap<String, SpriteSpecifier>,
+    pub sprites: HashMap<String, SpriteEntry>,
+    /// Extra palettes, each generated as its own set of pages with the same
+    /// layout.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub themes: HashMap<String, Theme>,
+}
+
+/// Colour overrides for one theme of a spritesheet.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Theme {
+    /// Replaces every colour of every sprite in this theme.
+    pub color: Option<HexColor>,
+    /// Multiplies every colour of every sprite in this theme.
+    pub tint: Option<HexColor>,
+    /// Overrides for individual sprites in this theme, by key.
+    pub sprites: HashMap<String, ThemeColors>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ThemeColors {
+    pub color: Option<HexColor>,
+    pub tint: Option<HexColor>,
+}
+
+impl Theme {
+    /// The `color` and `tint` a sprite is drawn with in this theme, falling
+    /// back to the sprite's own.
+    fn colors(&self, key: &str, entry: &SpriteEntry, spritegen: &Spritegen) -> ThemeColors {
+        let sprite = self.sprites.get(key).copied().unwrap_or_default();
+
+        ThemeColors {
+            color: sprite
+                .color
+                .or(self.color)
+                .or(entry.options.color)
+                .or(spritegen.color),
+            tint: sprite
+                .tint
+                .or(self.tint)
+                .or(entry.options.tint)
+                .or(spritegen.tint),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 #[cfg_attr(feature = "serde", serde(default))]
 pub struct Spritegen {
+    /// Shorthand for setting both `width` and `height`.
     pub spritesheet_size: u32,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
     pub sprites_per_row: u32,
+    /// Defaults to `grid` when `sprites_per_row` is set, so spritesheets
+    /// written before `max_rects` existed keep their layout, and to
+    /// `max_rects` otherwise.
+    pub packer: Option<Packer>,
+    /// Only used by the `max_rects` packer.
+    pub heuristic: MaxRectsHeuristic,
+    /// Lets the `max_rects` packer rotate sprites 90 degrees clockwise when it
+    /// saves space.
+    pub allow_rotation: bool,
+    /// Picks the smallest spritesheet that fits every sprite instead of using
+    /// `spritesheet_size`, `width` and `height`.
+    pub auto_size: bool,
+    /// Only lets `auto_size` pick power of two sizes.
+    pub power_of_two: bool,
+    /// The largest size `auto_size` can pick, after which sprites spill onto
+    /// more spritesheets.
+    pub max_size: Option<u32>,
+    /// Empty pixels between sprites.
+    pub padding: u32,
+    /// How many pixels to repeat the border of each sprite outward by.
+    pub extrude: u32,
+    /// Fails instead of skipping sprites whose source couldn't be fetched.
+    pub strict: bool,
+    /// How many sprites to fetch at once.
+    #[cfg_attr(feature = "serde", serde(default = "default_max_concurrent_fetches"))]
+    pub max_concurrent_fetches: usize,
+    /// How sources are drawn into sprites whose aspect ratio doesn't match,
+    /// unless a sprite sets its own.
+    pub fit: Fit,
+    /// Where sources sit in their sprite when `fit` leaves space or crops.
+    pub align: Align,
+    /// Replaces every colour of every sprite, keeping their transparency.
+    pub color: Option<HexColor>,
+    /// Multiplies every colour of every sprite.
+    pub tint: Option<HexColor>,
+    /// Crops the transparent borders off of every sprite before packing.
+    pub trim: bool,
+    /// Fills transparent pixels with the colour of their nearest visible
+    /// neighbours when saving spritesheets, to avoid dark halos when scaled.
+    #[cfg_attr(feature = "serde", serde(default = "default_true"))]
+    pub alpha_bleed: bool,
     #[cfg_attr(feature = "serde", serde(default = "default_name_format"))]
     pub name_format: String,
+    /// What to do with sprites whose source couldn't be fetched.
+    pub on_error: OnError,
+    /// Extra states of every sprite, such as `disabled` or `hover`, by name.
+    /// Each state is packed as its own sprite.
+    pub states: BTreeMap<String, StateFilter>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OnError {
+    /// Leaves the sprite out of the spritesheet.
+    #[default]
+    Skip,
+    /// Packs an obviously broken placeholder under the sprite's key, so
+    /// codegen keys stay the same.
+    Placeholder,
+}
+
+/// Every field outputs write for a sprite, which its states are written next
+/// to.
+const SPRITE_FIELDS: [&str; 11] = [
+    "spritesheet",
+    "x",
+    "y",
+    "width",
+    "height",
+    "offset_x",
+    "offset_y",
+    "source_width",
+    "source_height",
+    "rotated",
+    "tags",
+];
+
+/// The size of placeholders for sprites that don't set their own size.
+const PLACEHOLDER_SIZE: u32 = 64;
+
+#[cfg(feature = "serde")]
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn default_max_concurrent_fetches() -> usize {
+    16
 }
 
 #[cfg(feature = "serde")]
@@ -37,23 +191,191 @@ fn default_name_format() -> String {
     "$name$index".to_string()
 }
 
+impl Spritegen {
+    /// The packer to use, picking one from `sprites_per_row` when `packer`
+    /// isn't set.
+    pub fn packer(&self) -> Packer {
+        self.packer.unwrap_or(match self.sprites_per_row {
+            0 => Packer::MaxRects,
+            _ => Packer::Grid,
+        })
+    }
+
+    pub fn pack_options(&self) -> PackOptions {
+        PackOptions {
+            padding: self.padding,
+            heuristic: self.heuristic,
+            allow_rotation: self.allow_rotation,
+        }
+    }
+
+    /// The width and height of every spritesheet page.
+    pub fn page_size(&self) -> (u32, u32) {
+        (
+            self.width.unwrap_or(self.spritesheet_size),
+            self.height.unwrap_or(self.spritesheet_size),
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct Config {
     pub spritesheets: HashMap<String, SpritesheetSpecifier>,
+    /// Settings shared by every sprite of each source.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sources: SourcesConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cache: CacheConfig,
 }
 
-pub struct Sprite {
-    pub pixmap_key: String,
+/// Where a sprite's pixels are on its page.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteRect {
     pub x: u32,
     pub y: u32,
     pub width: u32,
     pub height: u32,
 }
 
+#[derive(Default)]
+pub struct Sprite {
+    pub pixmap_key: String,
+    /// The packed rect on the page, which outputs should use as the sprite's
+    /// `ImageRectOffset` and `ImageRectSize`.
+    pub rect: SpriteRect,
+    /// How far in from the left of the untrimmed sprite this sprite starts.
+    pub offset_x: u32,
+    /// How far in from the top of the untrimmed sprite this sprite starts.
+    pub offset_y: u32,
+    /// The width of the sprite before it was trimmed.
+    pub source_width: u32,
+    /// The height of the sprite before it was trimmed.
+    pub source_height: u32,
+    /// Whether the sprite is stored rotated 90 degrees clockwise, in which case
+    /// the size of `rect` is that of the rotated sprite on the spritesheet.
+    pub rotated: bool,
+    /// The width of the source itself, before it was scaled to the sprite's
+    /// size. Rounded to whole pixels for vector sources.
+    pub intrinsic_width: u32,
+    /// The height of the source itself, before it was scaled to the sprite's
+    /// size. Rounded to whole pixels for vector sources.
+    pub intrinsic_height: u32,
+    /// The options the sprite was written with.
+    pub options: SpriteOptions,
+    /// The page this sprite is on in each theme, by theme name.
+    pub theme_pixmap_keys: BTreeMap<String, String>,
+    /// The filtered copies of this sprite, by state name. States don't have
+    /// states of their own.
+    pub states: BTreeMap<String, Sprite>,
+}
+
+/// A sprite whose source couldn't be fetched.
+#[derive(Debug, Clone)]
+pub struct FailedSprite {
+    pub key: String,
+    /// The kind of source, such as `luicide` or `path`.
+    pub source_type: String,
+    pub cause: String,
+    /// Whether a placeholder was packed in its place, rather than the sprite
+    /// being left out.
+    pub placeholder: bool,
+}
+
+/// A fetched sprite and the size it's drawn at.
+struct SizedSprite {
+    key: String,
+    entry: SpriteEntry,
+    source: SpriteSource,
+    width: u32,
+    height: u32,
+    /// Whether the source is a placeholder for one that couldn't be fetched,
+    /// which is never recoloured.
+    placeholder: bool,
+}
+
+impl SizedSprite {
+    /// The colours the sprite is drawn with by default.
+    fn colors(&self, spritegen: &Spritegen) -> ThemeColors {
+        match self.placeholder {
+            true => ThemeColors::default(),
+            false => ThemeColors {
+                color: self.entry.options.color.or(spritegen.color),
+                tint: self.entry.options.tint.or(spritegen.tint),
+            },
+        }
+    }
+
+    /// The colours the sprite is drawn with in `theme`.
+    fn theme_colors(&self, theme: &Theme, spritegen: &Spritegen) -> ThemeColors {
+        match self.placeholder {
+            true => ThemeColors::default(),
+            false => theme.colors(&self.key, &self.entry, spritegen),
+        }
+    }
+}
+
+/// A sprite and its states after rendering and trimming.
+struct TrimmedSprite {
+    pixmap: Pixmap,
+    /// The sprite's filtered states, in the order of `sprite_states`.
+    states: Vec<Pixmap>,
+    offset_x: u32,
+    offset_y: u32,
+    untrimmed_size: (u32, u32),
+}
+
+/// A sprite rendered at its final size, waiting to be packed.
+struct RenderedSprite<'a> {
+    key: &'a String,
+    /// Index into the deduplicated pixmaps, shared by identical sprites.
+    pixmap_index: usize,
+    offset_x: u32,
+    offset_y: u32,
+    source_width: u32,
+    source_height: u32,
+    intrinsic_width: u32,
+    intrinsic_height: u32,
+    options: &'a SpriteOptions,
+    /// Index into the deduplicated pixmaps of each of the sprite's states.
+    states: Vec<(&'a String, usize)>,
+}
+
+#[derive(Default)]
 pub struct Spritesheet {
+    /// The width of every page in `pixmaps`.
+    pub width: u32,
+    /// The height of every page in `pixmaps`.
+    pub height: u32,
     pub pixmaps: HashMap<String, Pixmap>,
     pub sprites: HashMap<String, Sprite>,
+    /// Whether outputs should alpha bleed `pixmaps` when saving them.
+    pub alpha_bleed: bool,
+    /// Whether sprites were trimmed, and outputs should include their offsets
+    /// and untrimmed sizes.
+    pub trimmed: bool,
+    /// Whether sprites may have been rotated, and outputs should include
+    /// whether they were.
+    pub allow_rotation: bool,
+    /// Whether any sprite has tags, and outputs should include them.
+    pub tagged: bool,
+    /// The names of every theme, in order. Each theme has its own copy of
+    /// every page in `pixmaps`.
+    pub themes: Vec<String>,
+    /// The names of every state any sprite has, in order.
+    pub states: Vec<String>,
+    /// Sprites whose source couldn't be fetched.
+    pub failed: Vec<FailedSprite>,
+}
+
+impl Spritesheet {
+    /// Whether every sprite has `state`, so outputs can type it as always
+    /// being there.
+    pub fn every_sprite_has_state(&self, state: &str) -> bool {
+        self.sprites
+            .values()
+            .all(|sprite| sprite.states.contains_key(state))
+    }
 }
 
 // TODO: proper asts
@@ -63,133 +385,1062 @@ pub(crate) fn format_spritegen_name(format: &str, name: &str, index: &usize) ->
         .replace("$index", index.to_string().as_str())
 }
 
+/// Adds `pixmap` to `pixmaps` unless an identical one with the same `key` is
+/// already there, returning its index and whether it was added. `keys` holds
+/// the key of every pixmap in `pixmaps`.
+fn insert_unique_pixmap<K: Hash + Eq>(
+    pixmaps: &mut Vec<Pixmap>,
+    keys: &mut Vec<K>,
+    pixmaps_by_hash: &mut HashMap<u64, Vec<usize>>,
+    pixmap: Pixmap,
+    key: K,
+) -> (usize, bool) {
+    let mut hasher = DefaultHasher::new();
+    (pixmap.width(), pixmap.height(), pixmap.data(), &key).hash(&mut hasher);
+
+    let candidates = pixmaps_by_hash.entry(hasher.finish()).or_default();
+    let existing = candidates.iter().copied().find(|&index| {
+        let other = &pixmaps[index];
+        other.width() == pixmap.width()
+            && other.height() == pixmap.height()
+            && other.data() == pixmap.data()
+            && keys[index] == key
+    });
+
+    match existing {
+        Some(index) => (index, false),
+        None => {
+            pixmaps.push(pixmap);
+            keys.push(key);
+            candidates.push(pixmaps.len() - 1);
+            (pixmaps.len() - 1, true)
+        }
+    }
+}
+
+/// Every state of a sprite, with its own states overriding those of the
+/// spritesheet.
+fn sprite_states<'a>(
+    entry: &'a SpriteEntry,
+    spritegen: &'a Spritegen,
+) -> BTreeMap<&'a String, &'a StateFilter> {
+    spritegen
+        .states
+        .iter()
+        .chain(&entry.options.states)
+        .collect()
+}
+
+/// Rasterizes a sprite at its final size with the given colours, turned by
+/// its `rotation`, but not yet trimmed.
+fn render_sprite(
+    sprite_key: &str,
+    entry: &SpriteEntry,
+    source: &SpriteSource,
+    (width, height): (u32, u32),
+    colors: ThemeColors,
+    spritegen: &Spritegen,
+) -> anyhow::Result<Pixmap> {
+    let quarter_turns = entry.options.quarter_turns().with_context(|| {
+        format!("rotation of sprite {sprite_key} must be a multiple of 90 degrees")
+    })?;
+
+    let source = source
+        .recolored(colors.color, colors.tint)
+        .with_context(|| format!("failed to recolor sprite {sprite_key}"))?;
+
+    source
+        .render(
+            width,
+            height,
+            entry.options.fit.unwrap_or(spritegen.fit),
+            entry.options.align.unwrap_or(spritegen.align),
+        )
+        .and_then(|pixmap| processing::rotate::rotate(&pixmap, quarter_turns))
+        .with_context(|| format!("failed to render sprite {sprite_key}"))
+}
+
+/// Renders a sprite again with a theme's colours, cropped at the offsets and
+/// to the size its default render was trimmed to, and filtered into a state.
+fn render_themed(
+    sprite: &SizedSprite,
+    (offset_x, offset_y): (u32, u32),
+    (width, height): (u32, u32),
+    filter: Option<StateFilter>,
+    colors: ThemeColors,
+    theme: &str,
+    spritegen: &Spritegen,
+) -> anyhow::Result<Pixmap> {
+    let themed = render_sprite(
+        &sprite.key,
+        &sprite.entry,
+        &sprite.source,
+        (sprite.width, sprite.height),
+        colors,
+        spritegen,
+    )?;
+
+    let crop = IntRect::from_xywh(offset_x as i32, offset_y as i32, width, height)
+        .with_context(|| format!("failed to crop sprite {} for theme {theme}", sprite.key))?;
+
+    // Recolouring shouldn't change which pixels are visible, but anything it
+    // made visible outside of the crop would silently be cut off.
+    if let Some(bounds) = processing::trim::visible_bounds(&themed)
+        && (bounds.left() < crop.left()
+            || bounds.top() < crop.top()
+            || bounds.right() > crop.right()
+            || bounds.bottom() > crop.bottom())
+    {
+        bail!(
+            "theme {theme} changes which pixels of sprite {} are visible, so it can't share its layout",
+            sprite.key
+        );
+    }
+
+    let mut themed = themed
+        .clone_rect(crop)
+        .with_context(|| format!("failed to crop sprite {} for theme {theme}", sprite.key))?;
+
+    if let Some(filter) = filter {
+        processing::filter::filter(&mut themed, &filter);
+    }
+
+    Ok(themed)
+}
+
+/// Runs `work` on the rayon thread pool and waits for it without blocking the
+/// async runtime, so other spritesheets keep fetching and rendering meanwhile.
+async fn on_thread_pool<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    let (sender, receiver) = oneshot::channel();
+    rayon::spawn(move || {
+        // Panics are sent back and resumed here, as if `work` ran in place.
+        let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(work)));
+    });
+
+    match receiver
+        .await
+        .expect("thread pool should send back every result")
+    {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Draws every unique pixmap into its rect, extruding its edges.
+fn composite(pages: &mut [Pixmap], pixmaps: &[Pixmap], rects: &[PackedRect], extrude: u32) {
+    for (pixmap, rect) in pixmaps.iter().zip(rects) {
+        let page = &mut pages[rect.page];
+
+        let transform = if rect.rotated {
+            Transform::from_rotate(90.0)
+                .post_translate((rect.x + pixmap.height()) as f32, rect.y as f32)
+        } else {
+            Transform::from_translate(rect.x as f32, rect.y as f32)
+        };
+
+        page.draw_pixmap(
+            0,
+            0,
+            pixmap.as_ref(),
+            &PixmapPaint::default(),
+            transform,
+            None,
+        );
+
+        processing::extrude::extrude(page, rect.x, rect.y, rect.width, rect.height, extrude);
+    }
+}
+
 pub async fn spritegen(
     name: &str,
     spritesheet: &SpritesheetSpecifier,
-    reqwest: reqwest::Client,
+    context: &FetchContext,
     #[cfg(feature = "bin")] progress: Option<&ProgressBar>,
 ) -> anyhow::Result<Spritesheet> {
-    let spritesheet_size = spritesheet.spritegen.spritesheet_size;
+    let spritegen = &spritesheet.spritegen;
+    let extrude = spritegen.extrude;
+    let pack_options = spritegen.pack_options();
+    let packer = spritegen.packer();
 
-    let mut pixmaps: HashMap<String, Pixmap> = HashMap::new();
-    let mut pixmap_index = 0;
-    let mut pixmap_key =
-        format_spritegen_name(&spritesheet.spritegen.name_format, name, &pixmap_index);
+    // The largest page a sprite has to fit in, if there is one.
+    let max_page_size = if spritegen.auto_size {
+        // Power of two pages are rounded down to fit in `max_size`, the same
+        // way the packer does.
+        spritegen
+            .max_size
+            .map(|size| match spritegen.power_of_two {
+                true => packers::prev_power_of_two(size),
+                false => size,
+            })
+            .map(|size| (size, size))
+    } else {
+        let (page_width, page_height) = spritegen.page_size();
+
+        if page_width == 0 || page_height == 0 {
+            bail!(
+                "spritesheet size is not set, set either spritesheet_size, width and height, or auto_size"
+            );
+        }
+
+        Some((page_width, page_height))
+    };
 
-    let mut current_spritesheet: Pixmap = Pixmap::new(spritesheet_size, spritesheet_size).unwrap();
+    // The grid packer keeps scaling sprites without an explicit size to fill
+    // a cell, so grid spritesheets written before sprites had their own size
+    // don't change.
+    let cell_size = match packer {
+        Packer::Grid if spritegen.sprites_per_row > 0 => {
+            if spritegen.auto_size {
+                bail!(
+                    "sprites_per_row needs a fixed spritesheet size and can't be used with auto_size"
+                );
+            }
 
-    let mut current_x: i32 = 0;
-    let mut current_y: i32 = 0;
-    let mut highest_y_in_row: i32 = 0;
+            Some(spritegen.page_size().0 / spritegen.sprites_per_row)
+        }
+        _ => None,
+    };
 
-    let sprite_size = spritesheet_size / spritesheet.spritegen.sprites_per_row;
+    let mut themes: Vec<(&String, &Theme)> = spritesheet.themes.iter().collect();
+    themes.sort_by(|lhs, rhs| lhs.0.cmp(rhs.0));
 
-    let sprites = spritesheet.sprites.clone();
-    let mut sorted_sprites: Vec<(&String, &crate::sources::SpriteSpecifier)> =
-        sprites.iter().collect();
+    if let Some((theme, _)) = themes
+        .iter()
+        .find(|(theme, _)| spritesheet.sprites.contains_key(*theme))
+    {
+        bail!("theme {theme} has the same name as a sprite");
+    }
+
+    // Outputs write fields in any casing, so states can't match a field in any
+    // of them either.
+    let uncased = |name: &str| name.to_lowercase().replace('_', "");
+    if let Some(state) = spritegen
+        .states
+        .keys()
+        .chain(
+            spritesheet
+                .sprites
+                .values()
+                .flat_map(|entry| entry.options.states.keys()),
+        )
+        .find(|state| {
+            SPRITE_FIELDS
+                .iter()
+                .any(|field| uncased(field) == uncased(state))
+        })
+    {
+        bail!("state {state} has the same name as a field of sprites");
+    }
+
+    let mut sorted_sprites: Vec<(&String, &SpriteEntry)> = spritesheet.sprites.iter().collect();
     sorted_sprites.sort_by(|lhs, rhs| lhs.0.cmp(rhs.0));
 
-    let mut sprites_for_spritesheet: HashMap<String, Sprite> = HashMap::new();
+    let mut rendered: Vec<RenderedSprite> = Vec::new();
 
-    for (sprite_key, specifier) in sorted_sprites {
-        #[cfg(feature = "bin")]
-        if let Some(progress) = progress {
-            progress.set_message(format!("Fetching {}...", sprite_key.clone()));
-            progress.inc(1);
-        }
+    // Identical sprites, like one icon listed under several keys, are only
+    // packed once and share the same rect.
+    let mut unique_pixmaps: Vec<Pixmap> = Vec::new();
+    let mut unique_pixmaps_by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut unique_paddings: Vec<u32> = Vec::new();
+    // Sprites only share a rect when they're also coloured and filtered the
+    // same in every theme. Each unique pixmap remembers which sprite it was
+    // rendered from, where it was trimmed and which state it is, so it can be
+    // rendered again for each theme.
+    let mut unique_keys: Vec<(Vec<ThemeColors>, Option<StateFilter>)> = Vec::new();
+    let mut unique_origins: Vec<(usize, u32, u32, Option<StateFilter>)> = Vec::new();
+    let mut deduplicated_count = 0;
+    let mut deduplicated_area: u64 = 0;
 
-        match specifier.fetch(reqwest.clone()).await {
+    // Fetches run concurrently, but come back in key order so the output
+    // doesn't change between runs.
+    let fetches: Vec<_> = sorted_sprites
+        .into_iter()
+        .map(|(sprite_key, entry)| async move {
+            #[cfg(feature = "bin")]
+            if let Some(progress) = progress {
+                progress.set_message(format!("Fetching {}...", sprite_key.clone()));
+            }
+
+            let source = entry.source.fetch(context).await;
+
+            #[cfg(feature = "bin")]
+            if let Some(progress) = progress {
+                progress.inc(1);
+            }
+
+            (sprite_key, entry, source)
+        })
+        .collect();
+    let fetched: Vec<(&String, &SpriteEntry, anyhow::Result<SpriteSource>)> = stream::iter(fetches)
+        .buffered(spritegen.max_concurrent_fetches.max(1))
+        .collect()
+        .await;
+
+    let mut sized: Vec<SizedSprite> = Vec::new();
+    let mut failed: Vec<FailedSprite> = Vec::new();
+    for (sprite_key, entry, source) in fetched {
+        match source {
             Ok(source) => {
-                #[cfg(feature = "bin")]
-                if let Some(progress) = progress {
-                    progress.set_message(format!("Rendering {}...", sprite_key.clone()));
-                    progress.inc(1);
-                }
+                let (intrinsic_width, intrinsic_height) = source.size();
+                let (width, height) = entry
+                    .options
+                    .size((intrinsic_width, intrinsic_height))
+                    .or(cell_size.map(|size| (size, size)))
+                    .unwrap_or((
+                        (intrinsic_width.round() as u32).max(1),
+                        (intrinsic_height.round() as u32).max(1),
+                    ));
 
-                let (width, height) = match &source {
-                    SpriteSource::Pixmap(pixmap) => (pixmap.width() as f32, pixmap.height() as f32),
-                    #[cfg(feature = "svg")]
-                    SpriteSource::Tree(tree) => {
-                        let size = tree.size();
-                        (size.width(), size.height())
-                    }
-                };
-
-                let transform =
-                    Transform::from_scale(sprite_size as f32 / width, sprite_size as f32 / height)
-                        .post_translate(current_x as f32, current_y as f32);
-
-                match source {
-                    SpriteSource::Pixmap(pixmap) => {
-                        current_spritesheet.draw_pixmap(
-                            0,
-                            0,
-                            pixmap.as_ref(),
-                            &PixmapPaint::default(),
-                            transform,
-                            None,
-                        );
-                    }
-                    #[cfg(feature = "svg")]
-                    SpriteSource::Tree(tree) => {
-                        resvg::render(&tree, transform, &mut current_spritesheet.as_mut());
-                    }
-                };
-
-                sprites_for_spritesheet.insert(sprite_key.clone(), Sprite {
-                    pixmap_key: pixmap_key.clone(),
-                    x: current_x as u32,
-                    y: current_y as u32,
-                    width: width as u32,
-                    height: height as u32,
+                sized.push(SizedSprite {
+                    key: sprite_key.clone(),
+                    entry: entry.clone(),
+                    source,
+                    width,
+                    height,
+                    placeholder: false,
                 });
+            }
+            // Icons that don't match the lockfile under --locked fail the
+            // whole run, whatever on_error and strict say.
+            Err(e) if e.downcast_ref::<LockError>().is_some() => {
+                return Err(e.context(format!("failed to fetch sprite {sprite_key}")));
+            }
+            Err(e) => {
+                warn!("failed to fetch sprite {}: {:#}", sprite_key, e);
+
+                let placeholder = spritegen.on_error == OnError::Placeholder;
+                if placeholder {
+                    // Without a source there's no intrinsic size, so sprites
+                    // that only set a scale get a default sized placeholder.
+                    let (width, height) = entry
+                        .options
+                        .size((PLACEHOLDER_SIZE as f32, PLACEHOLDER_SIZE as f32))
+                        .or(cell_size.map(|size| (size, size)))
+                        .unwrap_or((PLACEHOLDER_SIZE, PLACEHOLDER_SIZE));
+                    let pixmap = processing::placeholder::placeholder(width, height)
+                        .with_context(|| format!("failed to draw placeholder for {sprite_key}"))?;
 
-                let spritesheet_size_i32: i32 = spritesheet_size.try_into().unwrap();
-                let sprite_size_i32 = sprite_size as i32;
-
-                current_x += sprite_size_i32;
-
-                if highest_y_in_row < height as i32 {
-                    highest_y_in_row = height as i32;
-                };
-
-                if current_x >= spritesheet_size_i32 {
-                    current_x = 0;
-                    current_y += highest_y_in_row;
-                    highest_y_in_row = 0;
-
-                    if current_y >= spritesheet_size_i32 {
-                        current_x = 0;
-                        current_y = 0;
-                        pixmap_index += 1;
-                        pixmaps.insert(pixmap_key.clone(), current_spritesheet.clone());
-                        pixmap_key = format_spritegen_name(
-                            &spritesheet.spritegen.name_format,
-                            name,
-                            &pixmap_index,
-                        );
-                        current_spritesheet =
-                            Pixmap::new(spritesheet_size, spritesheet_size).unwrap();
+                    sized.push(SizedSprite {
+                        key: sprite_key.clone(),
+                        entry: entry.clone(),
+                        source: SpriteSource::Pixmap(pixmap),
+                        width,
+                        height,
+                        placeholder: true,
+                    });
+                } else {
+                    #[cfg(feature = "bin")]
+                    if let Some(progress) = progress {
+                        progress.inc(1);
                     }
                 }
+
+                failed.push(FailedSprite {
+                    key: sprite_key.clone(),
+                    source_type: entry.source.source_type(),
+                    cause: format!("{e:#}"),
+                    placeholder,
+                });
             }
-            Err(e) => {
-                warn!("failed to fetch sprite {}: {}", sprite_key, e);
-                #[cfg(feature = "bin")]
-                if let Some(progress) = progress {
-                    progress.inc(1);
-                }
-                continue;
+        }
+    }
+
+    if spritegen.strict && !failed.is_empty() {
+        let failures = failed
+            .iter()
+            .map(|sprite| {
+                format!(
+                    "\n  {} ({}): {}",
+                    sprite.key, sprite.source_type, sprite.cause
+                )
+            })
+            .collect::<String>();
+
+        bail!(
+            "failed to fetch {} sprite(s) in {name}:{failures}",
+            failed.len()
+        );
+    }
+
+    let sized = Arc::new(sized);
+
+    // Rasterizing is the slow part, so every sprite is rendered to its own
+    // pixmap across the thread pool. The results are still in key order.
+    let trimmed: Vec<TrimmedSprite> = on_thread_pool({
+        let sized = sized.clone();
+        let spritegen = spritegen.clone();
+        #[cfg(feature = "bin")]
+        let progress = progress.cloned();
+
+        move || {
+            sized
+                .par_iter()
+                .map(|sprite| {
+                    #[cfg(feature = "bin")]
+                    if let Some(progress) = &progress {
+                        progress.set_message(format!("Rendering {}...", sprite.key));
+                    }
+
+                    let entry = &sprite.entry;
+                    let pixmap = render_sprite(
+                        &sprite.key,
+                        entry,
+                        &sprite.source,
+                        (sprite.width, sprite.height),
+                        sprite.colors(&spritegen),
+                        &spritegen,
+                    )?;
+                    let untrimmed_size = (pixmap.width(), pixmap.height());
+
+                    #[cfg(feature = "bin")]
+                    if let Some(progress) = &progress {
+                        progress.inc(1);
+                    }
+
+                    let (pixmap, offset_x, offset_y) = if spritegen.trim {
+                        processing::trim::trim(&pixmap)
+                    } else {
+                        (pixmap, 0, 0)
+                    };
+
+                    // States are filtered from the trimmed sprite, so they're
+                    // cropped the same and can share its offsets.
+                    let states = sprite_states(entry, &spritegen)
+                        .into_values()
+                        .map(|filter| {
+                            let mut state = pixmap.clone();
+                            processing::filter::filter(&mut state, filter);
+                            state
+                        })
+                        .collect();
+
+                    Ok(TrimmedSprite {
+                        pixmap,
+                        states,
+                        offset_x,
+                        offset_y,
+                        untrimmed_size,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()
+        }
+    })
+    .await?;
+
+    for (
+        sized_index,
+        (
+            sprite,
+            TrimmedSprite {
+                pixmap,
+                states: state_pixmaps,
+                offset_x,
+                offset_y,
+                untrimmed_size,
+            },
+        ),
+    ) in sized.iter().zip(trimmed).enumerate()
+    {
+        let (sprite_key, entry) = (&sprite.key, &sprite.entry);
+        let (intrinsic_width, intrinsic_height) = sprite.source.size();
+        let border = extrude + entry.options.padding;
+
+        if let Some((page_width, page_height)) = max_page_size
+            && !packer.fits(
+                (pixmap.width() + border * 2, pixmap.height() + border * 2),
+                page_width,
+                page_height,
+                &pack_options,
+            )
+        {
+            bail!(
+                "sprite {sprite_key} ({}x{}) is larger than the {page_width}x{page_height} spritesheet",
+                pixmap.width(),
+                pixmap.height()
+            );
+        }
+
+        let theme_colors: Vec<ThemeColors> = themes
+            .iter()
+            .map(|(_, theme)| sprite.theme_colors(theme, spritegen))
+            .collect();
+        let states: Vec<(&String, StateFilter)> = sprite_states(entry, spritegen)
+            .into_iter()
+            .map(|(state, filter)| (state, *filter))
+            .collect();
+
+        // Each state is packed like a sprite of its own, so it can land on a
+        // different page than its sprite and keeps its own pixmap key.
+        let mut pixmap_index = 0;
+        let mut state_indices = Vec::new();
+        for (state, pixmap) in iter::once(None)
+            .chain(states.iter().map(Some))
+            .zip(iter::once(pixmap).chain(state_pixmaps))
+        {
+            let area = pixmap.width() as u64 * pixmap.height() as u64;
+            let filter = state.map(|(_, filter)| *filter);
+            let (index, is_new) = insert_unique_pixmap(
+                &mut unique_pixmaps,
+                &mut unique_keys,
+                &mut unique_pixmaps_by_hash,
+                pixmap,
+                (theme_colors.clone(), filter),
+            );
+
+            if is_new {
+                unique_paddings.push(entry.options.padding);
+                unique_origins.push((sized_index, offset_x, offset_y, filter));
+            } else {
+                // Sprites sharing a rect get the most space any of them asked
+                // for.
+                unique_paddings[index] = unique_paddings[index].max(entry.options.padding);
+                deduplicated_count += 1;
+                deduplicated_area += area;
+            }
+
+            match state {
+                Some((state, _)) => state_indices.push((*state, index)),
+                None => pixmap_index = index,
             }
         }
+
+        rendered.push(RenderedSprite {
+            key: sprite_key,
+            pixmap_index,
+            offset_x,
+            offset_y,
+            source_width: untrimmed_size.0,
+            source_height: untrimmed_size.1,
+            intrinsic_width: intrinsic_width.round() as u32,
+            intrinsic_height: intrinsic_height.round() as u32,
+            options: &entry.options,
+            states: state_indices,
+        });
+    }
+
+    if deduplicated_count > 0 {
+        info!(
+            "deduplicated {deduplicated_count} sprite(s) in {name}, saving {deduplicated_area} pixels"
+        );
     }
 
-    if current_x > 0 || current_y > 0 || pixmaps.is_empty() {
-        pixmaps.insert(pixmap_key, current_spritesheet.clone());
+    let sizes: Vec<(u32, u32)> = unique_pixmaps
+        .iter()
+        .zip(&unique_paddings)
+        .map(|(pixmap, padding)| {
+            let border = extrude + padding;
+            (pixmap.width() + border * 2, pixmap.height() + border * 2)
+        })
+        .collect();
+
+    let (page_width, page_height, rects) = if spritegen.auto_size {
+        packer
+            .pack_smallest(
+                &sizes,
+                spritegen.max_size,
+                spritegen.power_of_two,
+                &pack_options,
+            )
+            .context("failed to pack sprites")?
+    } else {
+        let (page_width, page_height) = spritegen.page_size();
+        let rects = packer
+            .pack(&sizes, page_width, page_height, &pack_options)
+            .context("failed to pack sprites")?;
+
+        (page_width, page_height, rects)
+    };
+
+    let page_count = rects.iter().map(|rect| rect.page + 1).max().unwrap_or(1);
+    info!("packed {name} into {page_count} {page_width}x{page_height} spritesheet(s)");
+    let mut pages = (0..page_count)
+        .map(|_| Pixmap::new(page_width, page_height))
+        .collect::<Option<Vec<Pixmap>>>()
+        .context("failed to create spritesheet")?;
+
+    let mut sprites_for_spritesheet: HashMap<String, Sprite> = HashMap::new();
+
+    // The slots include the extruded border and any per-sprite padding, the
+    // sprites themselves sit inside.
+    let rects: Vec<PackedRect> = rects
+        .into_iter()
+        .zip(&unique_paddings)
+        .map(|(slot, padding)| {
+            let border = extrude + padding;
+
+            PackedRect {
+                x: slot.x + border,
+                y: slot.y + border,
+                width: slot.width - border * 2,
+                height: slot.height - border * 2,
+                ..slot
+            }
+        })
+        .collect();
+
+    composite(&mut pages, &unique_pixmaps, &rects, extrude);
+
+    let mut pixmaps: HashMap<String, Pixmap> = HashMap::new();
+    let mut theme_names: Vec<String> = Vec::new();
+
+    // Recolouring keeps transparency the same, so every theme is rendered and
+    // cropped exactly like the default pages and drawn into the same rects.
+    let theme_pixmaps: Vec<Vec<Pixmap>> = on_thread_pool({
+        let sized = sized.clone();
+        let spritegen = spritegen.clone();
+        let theme_names: Vec<String> = themes.iter().map(|(theme, _)| theme.to_string()).collect();
+        #[cfg(feature = "bin")]
+        let progress = progress.cloned();
+        // Sprites that were skipped count too, so the bar still fills up.
+        #[cfg(feature = "bin")]
+        let sprite_count = spritesheet.sprites.len() as u64;
+
+        move || {
+            theme_names
+                .iter()
+                .enumerate()
+                .map(|(theme_index, theme)| {
+                    let theme_pixmaps = unique_origins
+                        .par_iter()
+                        .zip(&unique_pixmaps)
+                        .zip(&unique_keys)
+                        .map(
+                            |(
+                                (&(sized_index, offset_x, offset_y, filter), pixmap),
+                                (colors, _),
+                            )| {
+                                let sprite = &sized[sized_index];
+
+                                #[cfg(feature = "bin")]
+                                if let Some(progress) = &progress {
+                                    progress.set_message(format!(
+                                        "Rendering {} ({theme})...",
+                                        sprite.key
+                                    ));
+                                }
+
+                                render_themed(
+                                    sprite,
+                                    (offset_x, offset_y),
+                                    (pixmap.width(), pixmap.height()),
+                                    filter,
+                                    colors[theme_index],
+                                    theme,
+                                    &spritegen,
+                                )
+                            },
+                        )
+                        .collect::<anyhow::Result<Vec<Pixmap>>>()?;
+
+                    #[cfg(feature = "bin")]
+                    if let Some(progress) = &progress {
+                        progress.inc(sprite_count);
+                    }
+
+                    Ok(theme_pixmaps)
+                })
+                .collect::<anyhow::Result<_>>()
+        }
+    })
+    .await?;
+
+    for ((theme, _), theme_pixmaps) in themes.iter().zip(theme_pixmaps) {
+        let mut theme_pages = (0..page_count)
+            .map(|_| Pixmap::new(page_width, page_height))
+            .collect::<Option<Vec<Pixmap>>>()
+            .context("failed to create spritesheet")?;
+        composite(&mut theme_pages, &theme_pixmaps, &rects, extrude);
+
+        let theme_name = format!("{name}_{theme}");
+        for (index, page) in theme_pages.into_iter().enumerate() {
+            pixmaps.insert(
+                format_spritegen_name(&spritegen.name_format, &theme_name, &index),
+                page,
+            );
+        }
+
+        theme_names.push(theme.to_string());
     }
 
+    let tagged = rendered
+        .iter()
+        .any(|sprite| !sprite.options.tags.is_empty());
+    let states: BTreeSet<&String> = rendered
+        .iter()
+        .flat_map(|sprite| sprite.states.iter().map(|(state, _)| *state))
+        .collect();
+
+    // States are sprites of their own, only packed into different rects.
+    let to_sprite = |sprite: &RenderedSprite, pixmap_index: usize| {
+        let rect = rects[pixmap_index];
+
+        Sprite {
+            pixmap_key: format_spritegen_name(&spritegen.name_format, name, &rect.page),
+            rect: SpriteRect {
+                x: rect.x,
+                y: rect.y,
+                width: rect.width,
+                height: rect.height,
+            },
+            offset_x: sprite.offset_x,
+            offset_y: sprite.offset_y,
+            source_width: sprite.source_width,
+            source_height: sprite.source_height,
+            rotated: rect.rotated,
+            intrinsic_width: sprite.intrinsic_width,
+            intrinsic_height: sprite.intrinsic_height,
+            options: sprite.options.clone(),
+            theme_pixmap_keys: themes
+                .iter()
+                .map(|(theme, _)| {
+                    (
+                        theme.to_string(),
+                        format_spritegen_name(
+                            &spritegen.name_format,
+                            &format!("{name}_{theme}"),
+                            &rect.page,
+                        ),
+                    )
+                })
+                .collect(),
+            states: BTreeMap::new(),
+        }
+    };
+
+    for sprite in &rendered {
+        let mut sprite_for_spritesheet = to_sprite(sprite, sprite.pixmap_index);
+        sprite_for_spritesheet.states = sprite
+            .states
+            .iter()
+            .map(|&(state, pixmap_index)| (state.clone(), to_sprite(sprite, pixmap_index)))
+            .collect();
+
+        sprites_for_spritesheet.insert(sprite.key.clone(), sprite_for_spritesheet);
+    }
+
+    pixmaps.extend(pages.into_iter().enumerate().map(|(index, page)| {
+        (
+            format_spritegen_name(&spritegen.name_format, name, &index),
+            page,
+        )
+    }));
+
     Ok(Spritesheet {
+        width: page_width,
+        height: page_height,
         pixmaps,
         sprites: sprites_for_spritesheet,
+        alpha_bleed: spritegen.alpha_bleed,
+        trimmed: spritegen.trim,
+        allow_rotation: spritegen.allow_rotation && packer == Packer::MaxRects,
+        tagged,
+        themes: theme_names,
+        states: states.into_iter().cloned().collect(),
+        failed,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lockfile::Pins,
+        sources::fetch::{CacheConfig, TestServer},
+    };
+    use futures::executor::block_on;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::atomic::Ordering,
+        time::Duration,
+    };
+    use tiny_skia::{Color, PremultipliedColorU8};
+
+    /// A fresh directory for a test to write sources into.
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("springroll-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Saves a red `width` by `height` png into `dir`, with the pixels in
+    /// `holes` left transparent.
+    fn save_png(dir: &Path, name: &str, (width, height): (u32, u32), holes: &[(u32, u32)]) {
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+        pixmap.fill(Color::from_rgba8(255, 0, 0, 255));
+        for &(x, y) in holes {
+            pixmap.pixels_mut()[(y * width + x) as usize] = PremultipliedColorU8::TRANSPARENT;
+        }
+        pixmap.save_png(dir.join(format!("{name}.png"))).unwrap();
+    }
+
+    fn generate(spritesheet: &str) -> anyhow::Result<Spritesheet> {
+        block_on(generate_async(spritesheet))
+    }
+
+    /// Generates a spritesheet from TOML, for tests that fetch over HTTP and
+    /// so need a tokio runtime.
+    async fn generate_async(spritesheet: &str) -> anyhow::Result<Spritesheet> {
+        let spritesheet: SpritesheetSpecifier = toml::from_str(spritesheet).unwrap();
+        let context = FetchContext::new(
+            SourcesConfig::default(),
+            &CacheConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            false,
+            Pins::default(),
+        )
+        .unwrap();
+
+        #[cfg(feature = "bin")]
+        return spritegen("icons", &spritesheet, &context, None).await;
+        #[cfg(not(feature = "bin"))]
+        return spritegen("icons", &spritesheet, &context).await;
+    }
+
+    #[test]
+    fn deduplicates_identical_sprites() {
+        let dir = test_dir("deduplicates");
+        save_png(&dir, "a", (4, 4), &[]);
+        save_png(&dir, "b", (4, 4), &[]);
+        save_png(&dir, "c", (4, 4), &[(3, 3)]);
+
+        let spritesheet = generate(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nauto_size = true\
+            \n[sprites]\
+            \na = {{ path = \"{0}/a.png\" }}\
+            \nb = {{ path = \"{0}/b.png\" }}\
+            \nc = {{ path = \"{0}/c.png\" }}",
+            dir.display()
+        ))
+        .unwrap();
+
+        let rect = |key: &str| spritesheet.sprites[key].rect;
+        assert_eq!(rect("a"), rect("b"));
+        assert_ne!(rect("a"), rect("c"));
+    }
+
+    #[test]
+    fn themes_share_the_trimmed_layout() {
+        let dir = test_dir("themes");
+        save_png(&dir, "square", (4, 4), &[(0, 0), (1, 0), (2, 0), (3, 0)]);
+
+        let spritesheet = generate(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nauto_size = true\
+            \ntrim = true\
+            \non_error = \"placeholder\"\
+            \n[sprites]\
+            \nsquare = {{ path = \"{0}/square.png\" }}\
+            \nmissing = {{ path = \"{0}/missing.png\", width = 2, height = 2 }}\
+            \n[themes.dark]\
+            \ncolor = \"#0000ff\"",
+            dir.display()
+        ))
+        .unwrap();
+
+        let square = &spritesheet.sprites["square"];
+        assert_eq!((square.offset_x, square.offset_y), (0, 1));
+        assert_eq!((square.rect.width, square.rect.height), (4, 3));
+        assert_eq!(square.theme_pixmap_keys["dark"], "icons_dark0");
+
+        let pixel = |page: &str, sprite: &Sprite| {
+            let pixel = spritesheet.pixmaps[page]
+                .pixel(sprite.rect.x, sprite.rect.y)
+                .unwrap();
+            (pixel.red(), pixel.green(), pixel.blue(), pixel.alpha())
+        };
+        assert_eq!(pixel("icons0", square), (255, 0, 0, 255));
+        assert_eq!(pixel("icons_dark0", square), (0, 0, 255, 255));
+
+        // Placeholders look the same in every theme.
+        let missing = &spritesheet.sprites["missing"];
+        assert_eq!(pixel("icons0", missing), pixel("icons_dark0", missing));
+    }
+
+    #[test]
+    fn packs_states_onto_their_own_pages() {
+        let dir = test_dir("states");
+        save_png(&dir, "square", (4, 4), &[]);
+
+        let spritesheet = generate(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nspritesheet_size = 4\
+            \nstates = {{ disabled = {{ opacity = 0.5 }} }}\
+            \n[sprites]\
+            \nsquare = {{ path = \"{0}/square.png\" }}",
+            dir.display()
+        ))
+        .unwrap();
+
+        // A page only fits one sprite, so the state spills onto a second one.
+        let square = &spritesheet.sprites["square"];
+        let disabled = &square.states["disabled"];
+        assert_eq!(square.pixmap_key, "icons0");
+        assert_eq!(disabled.pixmap_key, "icons1");
+        assert_eq!(
+            spritesheet.pixmaps["icons1"].pixel(0, 0).unwrap().alpha(),
+            128
+        );
+    }
+
+    #[test]
+    fn rejects_states_named_after_fields() {
+        for state in ["width", "offsetX", "SOURCE_HEIGHT"] {
+            let error = generate(&format!(
+                "outputs = []\
+                \n[spritegen]\
+                \nauto_size = true\
+                \nstates = {{ {state} = {{ opacity = 0.5 }} }}\
+                \n[sprites]"
+            ))
+            .err()
+            .unwrap();
+
+            assert!(error.to_string().contains("same name as a field"));
+        }
+    }
+
+    #[test]
+    fn names_sprites_too_large_for_power_of_two_pages() {
+        let dir = test_dir("too-large");
+        save_png(&dir, "big", (600, 600), &[]);
+
+        let error = generate(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nauto_size = true\
+            \nmax_size = 1000\
+            \npower_of_two = true\
+            \n[sprites]\
+            \nbig = {{ path = \"{}/big.png\" }}",
+            dir.display()
+        ))
+        .err()
+        .unwrap();
+
+        assert!(
+            error
+                .to_string()
+                .contains("sprite big (600x600) is larger than the 512x512 spritesheet")
+        );
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_fetches_and_keeps_key_order() {
+        // Each icon is a different colour so they aren't deduplicated.
+        let svg = |color| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><rect width="4" height="4" fill="{color}"/></svg>"#
+            )
+        };
+        let (red, green, blue, black) = (svg("red"), svg("lime"), svg("blue"), svg("black"));
+        // Earlier keys take longer, so they'd come back last if fetches were
+        // collected as they finish.
+        let server = TestServer::new(&[
+            ("a.svg", &red, Duration::from_millis(150)),
+            ("b.svg", &green, Duration::from_millis(100)),
+            ("c.svg", &blue, Duration::from_millis(50)),
+            ("d.svg", &black, Duration::ZERO),
+        ]);
+
+        let spritesheet = generate_async(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nspritesheet_size = 16\
+            \nsprites_per_row = 4\
+            \nmax_concurrent_fetches = 2\
+            \n[sprites]\
+            \nd = {{ luicide = \"d\", base_url = \"{0}\" }}\
+            \nc = {{ luicide = \"c\", base_url = \"{0}\" }}\
+            \nb = {{ luicide = \"b\", base_url = \"{0}\" }}\
+            \na = {{ luicide = \"a\", base_url = \"{0}\" }}",
+            server.url
+        ))
+        .await
+        .unwrap();
+
+        let x = |key: &str| spritesheet.sprites[key].rect.x;
+        assert_eq!([x("a"), x("b"), x("c"), x("d")], [0, 4, 8, 12]);
+        assert_eq!(server.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fails_strictly_or_packs_placeholders() {
+        let dir = test_dir("strict");
+        save_png(&dir, "square", (4, 4), &[]);
+
+        let spritesheet = |strict: bool| {
+            format!(
+                "outputs = []\
+                \n[spritegen]\
+                \nauto_size = true\
+                \nstrict = {strict}\
+                \non_error = \"placeholder\"\
+                \n[sprites]\
+                \nsquare = {{ path = \"{0}/square.png\" }}\
+                \nmissing = {{ path = \"{0}/missing.png\" }}",
+                dir.display()
+            )
+        };
+
+        let error = generate(&spritesheet(true)).err().unwrap().to_string();
+        // The rest of the cause is the OS's own message.
+        assert!(error.starts_with(
+            "failed to fetch 1 sprite(s) in icons:\
+            \n  missing (path): failed to load png file: "
+        ));
+
+        let spritesheet = generate(&spritesheet(false)).unwrap();
+        assert_eq!(spritesheet.failed.len(), 1);
+        assert_eq!(spritesheet.failed[0].key, "missing");
+        assert_eq!(spritesheet.failed[0].source_type, "path");
+        assert!(spritesheet.failed[0].placeholder);
+        assert!(spritesheet.sprites.contains_key("missing"));
+        assert!(spritesheet.sprites.contains_key("square"));
+    }
+
+    #[test]
+    fn rotates_tall_sprites_onto_wide_pages() {
+        let dir = test_dir("rotates");
+        save_png(&dir, "tall", (2, 8), &[]);
+
+        let spritesheet = generate(&format!(
+            "outputs = []\
+            \n[spritegen]\
+            \nwidth = 32\
+            \nheight = 8\
+            \nallow_rotation = true\
+            \n[sprites]\
+            \ntall = {{ path = \"{}/tall.png\", width = 4, height = 16, padding = 1 }}",
+            dir.display()
+        ))
+        .unwrap();
+
+        // The sprite is drawn at 4x16 and turned to fit the page, inside its
+        // own padding, while keeping the size of its source.
+        let tall = &spritesheet.sprites["tall"];
+        assert!(tall.rotated);
+        assert_eq!(
+            tall.rect,
+            SpriteRect {
+                x: 1,
+                y: 1,
+                width: 16,
+                height: 4,
+            }
+        );
+        assert_eq!((tall.intrinsic_width, tall.intrinsic_height), (2, 8));
+
+        let page = &spritesheet.pixmaps[&tall.pixmap_key];
+        assert_eq!(page.pixel(0, 0).unwrap().alpha(), 0);
+        assert_eq!(page.pixel(1, 1).unwrap().alpha(), 255);
+        assert_eq!(page.pixel(17, 5).unwrap().alpha(), 0);
+    }
+}
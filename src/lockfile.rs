@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const LOCKFILE_NAME: &str = "Springroll.lock";
+
+/// The contents of `Springroll.lock`, pinning every icon fetched over HTTP to
+/// a hash of what was fetched.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Lockfile {
+    #[cfg_attr(feature = "serde", serde(rename = "pin"))]
+    pub pins: Vec<Pin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Pin {
+    pub url: String,
+    /// The SHA-256 of the fetched bytes, in lowercase hex.
+    pub sha256: String,
+}
+
+impl Lockfile {
+    /// Writes the lockfile as TOML, with pins sorted by URL so it diffs well.
+    #[cfg(feature = "serde")]
+    pub fn to_toml(&self) -> Result<String> {
+        let mut sorted = self.clone();
+        sorted.pins.sort_by(|lhs, rhs| lhs.url.cmp(&rhs.url));
+
+        Ok(format!(
+            "# This file is automatically @generated by Springroll {}.\
+            \n# It is not intended for manual editing.\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            toml::to_string(&sorted).context("failed to serialize lockfile")?
+        ))
+    }
+}
+
+/// How fetched icons are checked against the lockfile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Pins new icons. Icons that changed are still used as fetched, and only
+    /// reported, keeping their old pin.
+    #[default]
+    Verify,
+    /// Fails icons that changed or aren't pinned yet.
+    Locked,
+    /// Pins whatever is fetched, replacing old pins.
+    Update,
+}
+
+/// An icon that doesn't match the lockfile under `--locked`. Unlike other fetch
+/// errors, this fails the whole run instead of only its sprite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockError {
+    Changed {
+        url: String,
+        pinned: String,
+        hash: String,
+    },
+    Unpinned {
+        url: String,
+    },
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Changed { url, pinned, hash } => write!(
+                f,
+                "{url} changed since it was locked, expected sha256 {pinned} but got {hash}. Run springroll update to accept it"
+            ),
+            Self::Unpinned { url } => write!(
+                f,
+                "{url} isn't in {LOCKFILE_NAME}. Run springroll update to pin it"
+            ),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+/// Checks fetched icons against a lockfile, shared by every fetch of a run.
+#[derive(Debug, Clone, Default)]
+pub struct Pins {
+    mode: LockMode,
+    previous: BTreeMap<String, String>,
+    current: Arc<Mutex<BTreeMap<String, String>>>,
+    changed: Arc<Mutex<Vec<String>>>,
+}
+
+/// The SHA-256 of `bytes`, in lowercase hex.
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+impl Pins {
+    pub fn new(lockfile: &Lockfile, mode: LockMode) -> Self {
+        Self {
+            mode,
+            previous: lockfile
+                .pins
+                .iter()
+                .map(|pin| (pin.url.clone(), pin.sha256.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Checks the bytes fetched from `url` against its pin.
+    pub fn check(&self, url: &str, bytes: &[u8]) -> Result<()> {
+        let hash = sha256(bytes);
+
+        let pinned = match (self.previous.get(url), self.mode) {
+            (Some(pinned), _) if *pinned == hash => hash,
+            (_, LockMode::Update) | (None, LockMode::Verify) => hash,
+            (Some(pinned), LockMode::Verify) => {
+                warn!("{url} changed since it was locked, expected sha256 {pinned} but got {hash}");
+                self.changed.lock().unwrap().push(url.to_string());
+                pinned.clone()
+            }
+            (Some(pinned), LockMode::Locked) => {
+                self.changed.lock().unwrap().push(url.to_string());
+                return Err(LockError::Changed {
+                    url: url.to_string(),
+                    pinned: pinned.clone(),
+                    hash,
+                }
+                .into());
+            }
+            (None, LockMode::Locked) => {
+                self.changed.lock().unwrap().push(url.to_string());
+                return Err(LockError::Unpinned {
+                    url: url.to_string(),
+                }
+                .into());
+            }
+        };
+
+        self.current.lock().unwrap().insert(url.to_string(), pinned);
+
+        Ok(())
+    }
+
+    /// Every URL whose icon didn't match its pin, or wasn't pinned when
+    /// locked, in order.
+    pub fn changed(&self) -> Vec<String> {
+        let mut changed = self.changed.lock().unwrap().clone();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    /// The lockfile after this run. Pins that weren't fetched are kept unless
+    /// `prune` is set.
+    pub fn lockfile(&self, prune: bool) -> Lockfile {
+        let mut pins = match prune {
+            true => BTreeMap::new(),
+            false => self.previous.clone(),
+        };
+        pins.extend(self.current.lock().unwrap().clone());
+
+        Lockfile {
+            pins: pins
+                .into_iter()
+                .map(|(url, sha256)| Pin { url, sha256 })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile(url: &str, bytes: &[u8]) -> Lockfile {
+        Lockfile {
+            pins: vec![Pin {
+                url: url.to_string(),
+                sha256: sha256(bytes),
+            }],
+        }
+    }
+
+    #[test]
+    fn keeps_pins_of_changed_icons_unless_updating() {
+        let old = lockfile("https://example.com/a.svg", b"old");
+
+        let verify = Pins::new(&old, LockMode::Verify);
+        verify.check("https://example.com/a.svg", b"new").unwrap();
+        verify.check("https://example.com/b.svg", b"b").unwrap();
+        assert_eq!(verify.changed(), ["https://example.com/a.svg"]);
+        assert_eq!(verify.lockfile(false).pins[0], old.pins[0]);
+        assert_eq!(verify.lockfile(false).pins.len(), 2);
+
+        let locked = Pins::new(&old, LockMode::Locked);
+        let lock_error = |url, bytes| {
+            locked
+                .check(url, bytes)
+                .unwrap_err()
+                .downcast::<LockError>()
+                .unwrap()
+        };
+        assert!(matches!(
+            lock_error("https://example.com/a.svg", b"new"),
+            LockError::Changed { .. }
+        ));
+        assert!(matches!(
+            lock_error("https://example.com/b.svg", b"b"),
+            LockError::Unpinned { .. }
+        ));
+        assert!(locked.check("https://example.com/a.svg", b"old").is_ok());
+
+        let update = Pins::new(&old, LockMode::Update);
+        update.check("https://example.com/a.svg", b"new").unwrap();
+        assert_eq!(
+            update.lockfile(true),
+            lockfile("https://example.com/a.svg", b"new")
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_toml() {
+        let lockfile = Lockfile {
+            pins: vec![
+                Pin {
+                    url: "https://example.com/b \"quoted\".svg".to_string(),
+                    sha256: sha256(b"b"),
+                },
+                Pin {
+                    url: "https://example.com/a.svg".to_string(),
+                    sha256: sha256(b"a"),
+                },
+            ],
+        };
+
+        let toml = lockfile.to_toml().unwrap();
+        assert!(toml.starts_with("# This file is automatically @generated"));
+
+        let parsed: Lockfile = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.pins[0], lockfile.pins[1]);
+        assert_eq!(parsed.pins[1], lockfile.pins[0]);
+    }
+}
@@ -1,15 +1,20 @@
 use std::{collections::HashMap, fs};
 
-use anyhow::Context;
+use anyhow::{Context, bail};
 use clap::{
-    Parser,
+    Parser, Subcommand,
     builder::{Styles, styling::AnsiColor},
 };
 use clap_verbosity_flag::Verbosity;
 use console::style;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use itertools::Itertools;
-use springroll::{Config, SpritesheetSpecifier, spritegen};
+use springroll::{
+    Config, FailedSprite, SpritesheetSpecifier,
+    lockfile::{LOCKFILE_NAME, LockMode, Lockfile, Pins},
+    sources::fetch::{CacheConfig, FetchContext},
+    spritegen,
+};
 use tokio::task::JoinSet;
 
 const STYLES: Styles = Styles::styled()
@@ -40,8 +45,39 @@ pub struct Args {
     /// Name of the person to greet
     #[arg(long)]
     include: Option<Vec<String>>,
+    /// How many sprites to fetch at once, overriding `max_concurrent_fetches`
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Fail instead of skipping sprites that couldn't be fetched
+    #[arg(long)]
+    deny_warnings: bool,
+    /// Only use icons that are already cached, without going to the network
+    #[arg(long)]
+    offline: bool,
+    /// Fail when icons fetched over HTTP don't match Springroll.lock
+    #[arg(long)]
+    locked: bool,
     #[command(flatten)]
     verbosity: Verbosity,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the cache of fetched icons
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Generate spritesheets, pinning whatever is fetched in Springroll.lock
+    Update,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Delete every cached icon
+    Clean,
 }
 
 #[tokio::main]
@@ -51,10 +87,31 @@ async fn main() -> anyhow::Result<()> {
         .filter_level(args.verbosity.log_level_filter())
         .init();
 
-    let config: Config = toml::from_str(
-        &fs::read_to_string(CONFIG_FILE_NAME).context("failed to read config file")?,
-    )
-    .context("failed to parse config file")?;
+    if let Some(Command::Cache {
+        command: CacheCommand::Clean,
+    }) = args.command
+    {
+        return clean_cache();
+    }
+
+    let config = read_config()?;
+
+    let mode = match (&args.command, args.locked) {
+        (Some(Command::Update), true) => bail!("--locked can't be used with springroll update"),
+        (Some(Command::Update), false) => LockMode::Update,
+        (_, true) => LockMode::Locked,
+        (_, false) => LockMode::Verify,
+    };
+    let lockfile: Lockfile = match fs::exists(LOCKFILE_NAME)? {
+        true => {
+            toml::from_str(&fs::read_to_string(LOCKFILE_NAME).context("failed to read lockfile")?)
+                .context("failed to parse lockfile")?
+        }
+        false => Lockfile::default(),
+    };
+    let pins = Pins::new(&lockfile, mode);
+    // Pins of spritesheets that weren't generated are kept.
+    let prune = mode == LockMode::Update && args.include.is_none();
 
     let config_spritesheets = &config.spritesheets;
 
@@ -77,31 +134,124 @@ async fn main() -> anyhow::Result<()> {
         .map(|(k, v)| (k.clone(), v.clone()))
         .collect();
 
-    let reqwest = reqwest::Client::builder()
-        .build()
-        .context("failed to create reqwest client")?;
+    let context = FetchContext::new(
+        config.sources.clone(),
+        &config.cache,
+        args.offline,
+        pins.clone(),
+    )?;
 
-    let mut outputs = JoinSet::new();
-    let bars = MultiProgress::new();
+    let specs = specs
+        .into_iter()
+        .map(|(key, mut spec)| {
+            if let Some(jobs) = args.jobs {
+                spec.spritegen.max_concurrent_fetches = jobs;
+            }
+
+            if args.deny_warnings {
+                spec.spritegen.strict = true;
+            }
+
+            (key, spec)
+        })
+        .collect();
 
     println!("{}", style("Generating spritesheets...").dim());
+    build(specs, &context, &MultiProgress::new()).await?;
+
+    // Icons that don't match under --locked already failed the build.
+    if mode == LockMode::Locked {
+        return Ok(());
+    }
+
+    let changed = pins.changed();
+    if !changed.is_empty() {
+        println!(
+            "{}",
+            style(format!(
+                "{} icon(s) changed since they were locked, run springroll update to accept them:",
+                changed.len()
+            ))
+            .yellow()
+        );
+
+        for url in &changed {
+            println!("  {}", style(url).dim());
+        }
+    }
+
+    let updated = pins.lockfile(prune);
+    if updated != lockfile {
+        fs::write(LOCKFILE_NAME, updated.to_toml()?).context("failed to write lockfile")?;
+    }
+
+    Ok(())
+}
+
+/// Generates every spritesheet, then writes their outputs. Outputs are only
+/// written once every spritesheet generated, so a failed run, such as one with
+/// icons that don't match the lockfile, leaves every output as it was.
+async fn build(
+    specs: HashMap<String, SpritesheetSpecifier>,
+    context: &FetchContext,
+    bars: &MultiProgress,
+) -> anyhow::Result<()> {
+    let mut generating = JoinSet::new();
 
     for (key, spec) in specs {
-        let reqwest = reqwest.clone();
+        let context = context.clone();
 
         let progress = bars.add(
-            ProgressBar::new((spec.sprites.len() * 2 + spec.outputs.len()) as u64)
-                .with_style(create_progress_style())
-                .with_prefix(key.clone()),
+            ProgressBar::new(
+                (spec.sprites.len() * (2 + spec.themes.len()) + spec.outputs.len()) as u64,
+            )
+            .with_style(create_progress_style())
+            .with_prefix(key.clone()),
         );
 
         progress.tick();
 
-        outputs.spawn(async move {
-            let spritesheet = spritegen(&key, &spec, reqwest, Some(&progress))
+        generating.spawn(async move {
+            let spritesheet = spritegen(&key, &spec, &context, Some(&progress))
                 .await
                 .context("failed to generate spritesheets")?;
 
+            anyhow::Ok((key, spec, spritesheet, progress))
+        });
+    }
+
+    // Failed sprites are still listed when a spritesheet errors, since that's
+    // when they're needed most.
+    let mut generated = Vec::new();
+    let mut failed: Vec<(String, FailedSprite)> = Vec::new();
+    let mut errors = Vec::new();
+    for result in generating.join_all().await {
+        match result {
+            Ok((key, spec, spritesheet, progress)) => {
+                failed.extend(
+                    spritesheet
+                        .failed
+                        .iter()
+                        .map(|sprite| (key.clone(), sprite.clone())),
+                );
+                generated.push((key, spec, spritesheet, progress));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if !failed.is_empty() {
+        print_failed(&mut failed);
+    }
+
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e);
+    }
+
+    let mut outputs = JoinSet::new();
+
+    for (key, spec, spritesheet, progress) in generated {
+        outputs.spawn(async move {
             for (index, output) in spec.outputs.iter().enumerate() {
                 progress.set_message(format!(
                     "Outputting #{} ({})...",
@@ -122,9 +272,245 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 
-    for result in outputs.join_all().await {
-        result.unwrap();
+    outputs
+        .join_all()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<()>>>()?;
+
+    Ok(())
+}
+
+fn read_config() -> anyhow::Result<Config> {
+    toml::from_str(&fs::read_to_string(CONFIG_FILE_NAME).context("failed to read config file")?)
+        .context("failed to parse config file")
+}
+
+fn clean_cache() -> anyhow::Result<()> {
+    // The cache can be cleaned outside of a project, where it's wherever it
+    // is by default.
+    let cache = match fs::exists(CONFIG_FILE_NAME)? {
+        true => read_config()?.cache,
+        false => CacheConfig::default(),
+    };
+
+    delete_cache(&cache)
+}
+
+fn delete_cache(cache: &CacheConfig) -> anyhow::Result<()> {
+    if !cache.dir.exists() {
+        println!("{}", style("Cache is already empty.").dim());
+        return Ok(());
     }
 
+    fs::remove_dir_all(&cache.dir)
+        .with_context(|| format!("failed to delete cache at {}", cache.dir.display()))?;
+    println!("Deleted cache at {}.", cache.dir.display());
+
     Ok(())
 }
+
+fn print_failed(failed: &mut [(String, FailedSprite)]) {
+    failed.sort_by(|lhs, rhs| (&lhs.0, &lhs.1.key).cmp(&(&rhs.0, &rhs.1.key)));
+
+    let headers = ["Spritesheet", "Sprite", "Source", "Replaced by"];
+    let rows: Vec<[&str; 4]> = failed
+        .iter()
+        .map(|(key, sprite)| {
+            [
+                key.as_str(),
+                &sprite.key,
+                &sprite.source_type,
+                match sprite.placeholder {
+                    true => "placeholder",
+                    false => "nothing",
+                },
+            ]
+        })
+        .collect();
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or(0)
+                .max(headers[column].len())
+        })
+        .collect();
+    let pad = |row: &[&str; 4]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}  "))
+            .collect::<String>()
+    };
+
+    println!(
+        "{}",
+        style(format!("Failed to fetch {} sprite(s):", failed.len())).yellow()
+    );
+    println!("  {}", style(format!("{}Cause", pad(&headers))).dim());
+
+    for (row, (_, sprite)) in rows.iter().zip(failed.iter()) {
+        println!("  {}{}", pad(row), style(&sprite.cause).dim());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        thread,
+    };
+
+    use indicatif::ProgressDrawTarget;
+    use springroll::{
+        lockfile::{LockError, Pin, sha256},
+        sources::fetch::SourcesConfig,
+    };
+
+    use super::*;
+
+    /// Serves `body` to every request on a local port, returning its URL.
+    fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn deletes_the_cache() {
+        let dir = std::env::temp_dir().join(format!("springroll-clean-{}", std::process::id()));
+        fs::create_dir_all(dir.join("content")).unwrap();
+        fs::write(dir.join("content").join("entry"), "cached").unwrap();
+
+        let cache = CacheConfig {
+            enabled: true,
+            dir: dir.clone(),
+        };
+        delete_cache(&cache).unwrap();
+        assert!(!dir.exists());
+
+        // Cleaning an empty cache isn't an error.
+        delete_cache(&cache).unwrap();
+    }
+
+    /// Builds an "icons" spritesheet of a Luicide icon whose pin doesn't match
+    /// what's served, and a "local" one that doesn't fetch anything, returning
+    /// the directory they're written to, the URL of the icon and the pins.
+    async fn build_with_stale_pin(
+        test: &str,
+        mode: LockMode,
+    ) -> (PathBuf, String, Pins, anyhow::Result<()>) {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"><rect width="4" height="4"/></svg>"#;
+        let url = serve(svg);
+
+        let dir = std::env::temp_dir().join(format!("springroll-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("icons")).unwrap();
+        fs::create_dir_all(dir.join("local")).unwrap();
+        fs::write(dir.join("square.svg"), svg).unwrap();
+
+        let spec = |sprite: String, output: &str| -> SpritesheetSpecifier {
+            toml::from_str(&format!(
+                "outputs = [{{ type = \"dir\", path = \"{}\" }}]\
+                \n[spritegen]\
+                \nauto_size = true\
+                \non_error = \"placeholder\"\
+                \n[sprites]\
+                \n{sprite}",
+                dir.join(output).display()
+            ))
+            .unwrap()
+        };
+        let specs = HashMap::from([
+            (
+                "icons".to_string(),
+                spec(
+                    format!("square = {{ luicide = \"square\", base_url = \"{url}\" }}"),
+                    "icons",
+                ),
+            ),
+            (
+                "local".to_string(),
+                spec(
+                    format!(
+                        "square = {{ path = \"{}\" }}",
+                        dir.join("square.svg").display()
+                    ),
+                    "local",
+                ),
+            ),
+        ]);
+
+        let lockfile = Lockfile {
+            pins: vec![Pin {
+                url: format!("{url}/square.svg"),
+                sha256: sha256(b"something else"),
+            }],
+        };
+        let pins = Pins::new(&lockfile, mode);
+        let context = FetchContext::new(
+            SourcesConfig::default(),
+            &CacheConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            false,
+            pins.clone(),
+        )
+        .unwrap();
+
+        let bars = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let result = build(specs, &context, &bars).await;
+
+        (dir, format!("{url}/square.svg"), pins, result)
+    }
+
+    #[tokio::test]
+    async fn locked_mismatch_writes_nothing() {
+        let (dir, _, _, result) = build_with_stale_pin("locked", LockMode::Locked).await;
+
+        // Even sprites that would otherwise be replaced by a placeholder, and
+        // spritesheets that don't fetch anything, aren't written.
+        assert!(result.unwrap_err().downcast_ref::<LockError>().is_some());
+        for output in ["icons", "local"] {
+            assert_eq!(fs::read_dir(dir.join(output)).unwrap().count(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_mismatch_writes_new_icons() {
+        let (dir, url, pins, result) = build_with_stale_pin("verify", LockMode::Verify).await;
+
+        // The changed icon is packed as fetched, only reported, and keeps its
+        // old pin.
+        result.unwrap();
+        let sheet = tiny_skia::Pixmap::load_png(dir.join("icons").join("icons0.png")).unwrap();
+        assert_eq!(sheet.pixel(0, 0).unwrap().alpha(), 255);
+        assert!(fs::read_dir(dir.join("local")).unwrap().count() > 0);
+
+        assert_eq!(pins.changed(), [url.as_str()]);
+        assert_eq!(
+            pins.lockfile(false).pins,
+            [Pin {
+                url,
+                sha256: sha256(b"something else"),
+            }]
+        );
+    }
+}
@@ -1,12 +1,13 @@
 use std::{fmt::Display, path::PathBuf};
 
 use anyhow::{Context, Result};
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::Deserialize;
 use std::fs;
 
-use super::create_disclaimer_comment;
+use super::{create_disclaimer_comment, quote_string};
 use crate::{Sprite, Spritesheet, match_casings, util::casings::Casing};
 
 #[derive(Default, Debug, Clone)]
@@ -40,7 +41,7 @@ fn default_pascal() -> Casing {
 }
 
 lazy_static! {
-    static ref LUAU_IDENTIFIER_REGEX: Regex = Regex::new("^([_a-zA-Z][_a-zA-Z0-9]?)+").unwrap();
+    static ref LUAU_IDENTIFIER_REGEX: Regex = Regex::new("^[_a-zA-Z][_a-zA-Z0-9]*$").unwrap();
 }
 
 fn is_luau_keyword(str: &str) -> bool {
@@ -85,7 +86,7 @@ fn wrap_luau_ident(str: &str) -> String {
     if is_luau_ident(str) {
         str.to_string()
     } else {
-        format!("[\"{str}\"]")
+        format!("[{}]", quote_string(str))
     }
 }
 
@@ -106,7 +107,7 @@ impl LuauCodegenOutput {
 
         code.push('\n');
         if self.include_prelude_types {
-            code.push_str(&self.prelude_types());
+            code.push_str(&self.prelude_types(spritesheet));
             code.push('\n');
         }
 
@@ -116,11 +117,36 @@ impl LuauCodegenOutput {
         body.push('{');
         body.push('\n');
 
-        for (key, sprite) in &spritesheet.sprites {
+        for (key, sprite) in spritesheet.sprites.iter().sorted_by_key(|(key, _)| *key) {
             body.push('\t');
             body.push_str(wrap_luau_ident(key).as_str());
             body.push_str(" = ");
-            body.push_str(&self.wrap_sprite(sprite));
+            body.push_str(&self.wrap_sprite(spritesheet, sprite, None));
+            body.push(',');
+            body.push('\n');
+        }
+
+        for theme in &spritesheet.themes {
+            let mut theme_body = String::new();
+            theme_body.push('{');
+            theme_body.push('\n');
+
+            for (key, sprite) in spritesheet.sprites.iter().sorted_by_key(|(key, _)| *key) {
+                theme_body.push_str("\t\t");
+                theme_body.push_str(wrap_luau_ident(key).as_str());
+                theme_body.push_str(" = ");
+                theme_body.push_str(&self.wrap_sprite(spritesheet, sprite, Some(theme)));
+                theme_body.push(',');
+                theme_body.push('\n');
+            }
+
+            theme_body.push('\t');
+            theme_body.push('}');
+
+            body.push('\t');
+            body.push_str(wrap_luau_ident(theme).as_str());
+            body.push_str(" = ");
+            body.push_str(&self.wrap_freeze(theme_body));
             body.push(',');
             body.push('\n');
         }
@@ -136,7 +162,7 @@ impl LuauCodegenOutput {
         code
     }
 
-    pub fn prelude_types(&self) -> String {
+    pub fn prelude_types(&self, spritesheet: &Spritesheet) -> String {
         let readonly_modifier = match self.new_luau_solver {
             true => "read ",
             false => "",
@@ -148,18 +174,79 @@ impl LuauCodegenOutput {
         let width = self.ident_width();
         let height = self.ident_height();
         let sprite = self.ident_sprite();
+        let sprite_state = self.ident_sprite_state();
+
+        let mut extra_fields = String::new();
+        if spritesheet.trimmed {
+            for field in [
+                self.ident_offset_x(),
+                self.ident_offset_y(),
+                self.ident_source_width(),
+                self.ident_source_height(),
+            ] {
+                extra_fields.push_str(&format!("\n\t{readonly_modifier} {field}: number,"));
+            }
+        }
 
-        format!(
+        if spritesheet.allow_rotation {
+            extra_fields.push_str(&format!(
+                "\n\t{readonly_modifier} {}: boolean,",
+                self.ident_rotated()
+            ));
+        }
+
+        if spritesheet.tagged {
+            extra_fields.push_str(&format!(
+                "\n\t{readonly_modifier} {}: {{ string }},",
+                self.ident_tags()
+            ));
+        }
+
+        let fields = format!(
             "\
-                export type {sprite} = {{\
                 \n\t{readonly_modifier} {image}: string,\
                 \n\t{readonly_modifier} {x}: number,\
                 \n\t{readonly_modifier} {y}: number,\
                 \n\t{readonly_modifier} {width}: number,\
                 \n\t{readonly_modifier} {height}: number,\
+                {extra_fields}\
+            "
+        );
+
+        // States have the same fields as sprites, but no states of their own.
+        let mut types = String::new();
+        let mut state_fields = String::new();
+        if !spritesheet.states.is_empty() {
+            types.push_str(&self.prelude_type(sprite_state, &fields));
+            types.push('\n');
+
+            for state in &spritesheet.states {
+                let optional = match spritesheet.every_sprite_has_state(state) {
+                    true => "",
+                    false => "?",
+                };
+
+                state_fields.push_str(&format!(
+                    "\n\t{readonly_modifier} {}: {sprite_state}{optional},",
+                    wrap_luau_ident(state)
+                ));
+            }
+        }
+
+        types.push_str(&self.prelude_type(sprite, &format!("{fields}{state_fields}")));
+        types
+    }
+
+    /// A sprite type with `fields`, and a function of the same name that
+    /// freezes tables of that type.
+    fn prelude_type(&self, ident: &str, fields: &str) -> String {
+        format!(
+            "\
+                export type {ident} = {{\
+                {fields}\
                 \n}}\
                 \n\
-                \nlocal function {sprite}(x: {sprite}): {sprite}\
+                \nlocal function {ident}(x: {ident}): {ident}\
                 \n\treturn {}\
                 \nend\
                 \n\
@@ -168,27 +255,103 @@ impl LuauCodegenOutput {
         )
     }
 
-    pub fn wrap_sprite(&self, sprite: &Sprite) -> String {
+    /// Writes a sprite and its states, on the default pages or those of
+    /// `theme`.
+    pub fn wrap_sprite(
+        &self,
+        spritesheet: &Spritesheet,
+        sprite: &Sprite,
+        theme: Option<&str>,
+    ) -> String {
+        let mut fields = self.sprite_fields(spritesheet, sprite, theme);
+
+        for (state, state_sprite) in &sprite.states {
+            let state_fields = self.sprite_fields(spritesheet, state_sprite, theme);
+
+            fields.push_str(&format!(
+                ", {} = {}",
+                wrap_luau_ident(state),
+                self.wrap_fields(self.ident_sprite_state(), state_fields)
+            ));
+        }
+
+        self.wrap_fields(self.ident_sprite(), fields)
+    }
+
+    fn wrap_fields(&self, constructor: &str, fields: String) -> String {
+        let inner = format!("{{ {fields} }}");
+
+        if self.include_prelude_types {
+            format!("{constructor}({inner})")
+        } else {
+            self.wrap_freeze(inner)
+        }
+    }
+
+    fn sprite_fields(
+        &self,
+        spritesheet: &Spritesheet,
+        sprite: &Sprite,
+        theme: Option<&str>,
+    ) -> String {
+        let pixmap_key = match theme {
+            Some(theme) => &sprite.theme_pixmap_keys[theme],
+            None => &sprite.pixmap_key,
+        };
+
         // typa shit pirate software would defend but okay
-        let inner = format!(
-            "{{ {} = \"{}\", {} = {}, {} = {}, {} = {}, {} = {} }}",
+        let mut fields = format!(
+            "{} = {}, {} = {}, {} = {}, {} = {}, {} = {}",
             self.ident_spritesheet(),
-            sprite.pixmap_key,
+            quote_string(pixmap_key),
             self.ident_x(),
-            sprite.x,
+            sprite.rect.x,
             self.ident_y(),
-            sprite.y,
+            sprite.rect.y,
             self.ident_width(),
-            sprite.width,
+            sprite.rect.width,
             self.ident_height(),
-            sprite.height,
+            sprite.rect.height,
         );
 
-        if self.include_prelude_types {
-            format!("{}({inner})", self.ident_sprite())
-        } else {
-            self.wrap_freeze(inner)
+        if spritesheet.trimmed {
+            fields.push_str(&format!(
+                ", {} = {}, {} = {}, {} = {}, {} = {}",
+                self.ident_offset_x(),
+                sprite.offset_x,
+                self.ident_offset_y(),
+                sprite.offset_y,
+                self.ident_source_width(),
+                sprite.source_width,
+                self.ident_source_height(),
+                sprite.source_height,
+            ));
+        }
+
+        if spritesheet.allow_rotation {
+            fields.push_str(&format!(", {} = {}", self.ident_rotated(), sprite.rotated));
+        }
+
+        if spritesheet.tagged {
+            let tags = sprite
+                .options
+                .tags
+                .iter()
+                .map(|tag| quote_string(tag))
+                .join(", ");
+            let tags = match tags.is_empty() {
+                true => "{}".to_string(),
+                false => format!("{{ {tags} }}"),
+            };
+
+            fields.push_str(&format!(
+                ", {} = {}",
+                self.ident_tags(),
+                self.wrap_freeze(tags)
+            ));
         }
+
+        fields
     }
 
     pub fn wrap_freeze(&self, inner: impl Display) -> String {
@@ -203,6 +366,10 @@ impl LuauCodegenOutput {
         match_casings!(self.type_casing => Sprite)
     }
 
+    pub fn ident_sprite_state(&self) -> &'static str {
+        match_casings!(self.type_casing => SpriteState)
+    }
+
     pub fn ident_spritesheet(&self) -> &'static str {
         match_casings!(self.field_casing => spritesheet)
     }
@@ -222,4 +389,129 @@ impl LuauCodegenOutput {
     pub fn ident_height(&self) -> &'static str {
         match_casings!(self.field_casing => height)
     }
+
+    pub fn ident_offset_x(&self) -> &'static str {
+        match_casings!(self.field_casing => offset_x)
+    }
+
+    pub fn ident_offset_y(&self) -> &'static str {
+        match_casings!(self.field_casing => offset_y)
+    }
+
+    pub fn ident_source_width(&self) -> &'static str {
+        match_casings!(self.field_casing => source_width)
+    }
+
+    pub fn ident_source_height(&self) -> &'static str {
+        match_casings!(self.field_casing => source_height)
+    }
+
+    pub fn ident_rotated(&self) -> &'static str {
+        match_casings!(self.field_casing => rotated)
+    }
+
+    pub fn ident_tags(&self) -> &'static str {
+        match_casings!(self.field_casing => tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outputs::codegen::{test_spritesheet, test_state_spritesheet};
+
+    #[test]
+    fn writes_trimmed_rotated_and_tagged_sprites() {
+        let output = LuauCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Snake,
+            field_casing: Casing::Pascal,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            output.codegen("icons", &test_spritesheet()),
+            format!(
+                "--!strict\n{}\n\
+                \nexport type sprite = {{\
+                \n\t Spritesheet: string,\
+                \n\t X: number,\
+                \n\t Y: number,\
+                \n\t Width: number,\
+                \n\t Height: number,\
+                \n\t OffsetX: number,\
+                \n\t OffsetY: number,\
+                \n\t SourceWidth: number,\
+                \n\t SourceHeight: number,\
+                \n\t Rotated: boolean,\
+                \n\t Tags: {{ string }},\
+                \n}}\
+                \n\
+                \nlocal function sprite(x: sprite): sprite\
+                \n\treturn x\
+                \nend\
+                \n\
+                \nlocal icons = {{\
+                \n\tclose = sprite({{ Spritesheet = \"icons0\", X = 1, Y = 2, Width = 3, Height = 4, \
+                OffsetX = 5, OffsetY = 6, SourceWidth = 7, SourceHeight = 8, Rotated = true, \
+                Tags = {{ \"ui\", \"say \\\"hi\\\" \\\\ bye\" }} }}),\
+                \n}}\
+                \n\
+                \nreturn icons\
+                \n",
+                create_disclaimer_comment("--")
+            )
+        );
+    }
+
+    #[test]
+    fn writes_states() {
+        let output = LuauCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Snake,
+            field_casing: Casing::Pascal,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            output.codegen("icons", &test_state_spritesheet()),
+            format!(
+                "--!strict\n{}\n\
+                \nexport type sprite_state = {{\
+                \n\t Spritesheet: string,\
+                \n\t X: number,\
+                \n\t Y: number,\
+                \n\t Width: number,\
+                \n\t Height: number,\
+                \n}}\
+                \n\
+                \nlocal function sprite_state(x: sprite_state): sprite_state\
+                \n\treturn x\
+                \nend\
+                \n\
+                \nexport type sprite = {{\
+                \n\t Spritesheet: string,\
+                \n\t X: number,\
+                \n\t Y: number,\
+                \n\t Width: number,\
+                \n\t Height: number,\
+                \n\t [\"hover-dark\"]: sprite_state?,\
+                \n}}\
+                \n\
+                \nlocal function sprite(x: sprite): sprite\
+                \n\treturn x\
+                \nend\
+                \n\
+                \nlocal icons = {{\
+                \n\tclose = sprite({{ Spritesheet = \"icons0\", X = 0, Y = 0, Width = 2, Height = 2, \
+                [\"hover-dark\"] = sprite_state({{ Spritesheet = \"icons1\", X = 2, Y = 0, Width = 2, Height = 2 }}) }}),\
+                \n\topen = sprite({{ Spritesheet = \"icons0\", X = 4, Y = 0, Width = 2, Height = 2 }}),\
+                \n}}\
+                \n\
+                \nreturn icons\
+                \n",
+                create_disclaimer_comment("--")
+            )
+        );
+    }
 }
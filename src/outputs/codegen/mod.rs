@@ -11,6 +11,12 @@ pub mod typescript_declarations;
 #[cfg(feature = "output_codegen_yaml")]
 pub mod yaml;
 
+#[cfg(any(feature = "output_codegen_ts", feature = "output_codegen_dts"))]
+use itertools::Itertools;
+
+#[cfg(any(feature = "output_codegen_ts", feature = "output_codegen_dts"))]
+use crate::{Spritesheet, match_casings, util::casings::Casing};
+
 #[inline]
 pub fn create_disclaimer_comment(comment_prefix: &'static str) -> String {
     format!(
@@ -20,6 +26,300 @@ pub fn create_disclaimer_comment(comment_prefix: &'static str) -> String {
     )
 }
 
+/// Writes `str` as a double quoted string literal, which Luau and TypeScript
+/// escape the same way.
+pub fn quote_string(str: &str) -> String {
+    let mut quoted = String::with_capacity(str.len() + 2);
+    quoted.push('"');
+
+    for char in str.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            char => quoted.push(char),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Writes `name` as a TypeScript property name, quoting it unless it's an
+/// identifier.
+#[cfg(any(feature = "output_codegen_ts", feature = "output_codegen_dts"))]
+pub fn typescript_property(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|char| char.is_ascii_alphabetic() || char == '_' || char == '$')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_' || char == '$');
+
+    match is_identifier {
+        true => name.to_string(),
+        false => quote_string(name),
+    }
+}
+
+/// The sprite, state and theme types shared by the TypeScript and TypeScript
+/// declarations outputs.
+#[cfg(any(feature = "output_codegen_ts", feature = "output_codegen_dts"))]
+pub struct TypeScriptTypes<'a> {
+    pub type_casing: &'a Casing,
+    pub field_casing: &'a Casing,
+    /// Whether states refer to the state type by name instead of spelling it
+    /// out.
+    pub include_prelude_types: bool,
+}
+
+#[cfg(any(feature = "output_codegen_ts", feature = "output_codegen_dts"))]
+impl TypeScriptTypes<'_> {
+    pub fn prelude_types(&self, spritesheet: &Spritesheet) -> String {
+        let mut types = String::new();
+
+        if !spritesheet.states.is_empty() {
+            types.push_str(&format!(
+                "export interface {} {}\n\n",
+                self.ident_sprite_state(),
+                self.state_type(spritesheet)
+            ));
+        }
+
+        types.push_str(&format!(
+            "export interface {} {}",
+            self.ident_sprite(),
+            self.sprite_type(spritesheet)
+        ));
+
+        if !spritesheet.themes.is_empty() {
+            types.push_str(&format!(
+                "\n\nexport interface {} {}",
+                self.ident_theme(),
+                self.theme_type(spritesheet)
+            ));
+        }
+
+        types
+    }
+
+    /// The type of a theme, which has every sprite on the pages of that
+    /// theme. Only used with prelude types, since it refers to the sprite
+    /// type by name.
+    pub fn theme_type(&self, spritesheet: &Spritesheet) -> String {
+        let sprite = self.ident_sprite();
+        let fields = spritesheet
+            .sprites
+            .keys()
+            .sorted()
+            .map(|key| format!("\n\treadonly {}: {sprite},", typescript_property(key)))
+            .collect::<String>();
+
+        format!("{{{fields}\n}}")
+    }
+
+    /// The type of a sprite, including its states.
+    pub fn sprite_type(&self, spritesheet: &Spritesheet) -> String {
+        let state_type = match self.include_prelude_types {
+            true => self.ident_sprite_state().to_string(),
+            false => self.state_type(spritesheet).replace("\n", "\n\t"),
+        };
+
+        let states = spritesheet
+            .states
+            .iter()
+            .map(|state| {
+                let optional = match spritesheet.every_sprite_has_state(state) {
+                    true => "",
+                    false => "?",
+                };
+
+                format!(
+                    "\n\treadonly {}{optional}: {state_type},",
+                    typescript_property(state)
+                )
+            })
+            .collect::<String>();
+
+        self.object_type(spritesheet, &states)
+    }
+
+    /// The type of a sprite's state, which has every field of a sprite but no
+    /// states of its own.
+    pub fn state_type(&self, spritesheet: &Spritesheet) -> String {
+        self.object_type(spritesheet, "")
+    }
+
+    /// The type with every field of a sprite, followed by `extra_fields`.
+    fn object_type(&self, spritesheet: &Spritesheet, extra_fields: &str) -> String {
+        let image = self.ident_spritesheet();
+        let x = self.ident_x();
+        let y = self.ident_y();
+        let width = self.ident_width();
+        let height = self.ident_height();
+
+        let mut optional_fields = String::new();
+        if spritesheet.trimmed {
+            for field in [
+                self.ident_offset_x(),
+                self.ident_offset_y(),
+                self.ident_source_width(),
+                self.ident_source_height(),
+            ] {
+                optional_fields.push_str(&format!("\n\treadonly {field}: number,"));
+            }
+        }
+
+        if spritesheet.allow_rotation {
+            optional_fields.push_str(&format!("\n\treadonly {}: boolean,", self.ident_rotated()));
+        }
+
+        if spritesheet.tagged {
+            optional_fields.push_str(&format!(
+                "\n\treadonly {}: readonly string[],",
+                self.ident_tags()
+            ));
+        }
+
+        format!(
+            "{{\
+                \n\treadonly {image}: string,\
+                \n\treadonly {x}: number,\
+                \n\treadonly {y}: number,\
+                \n\treadonly {width}: number,\
+                \n\treadonly {height}: number,\
+                {optional_fields}\
+                {extra_fields}\
+            \n}}"
+        )
+    }
+
+    pub fn ident_sprite(&self) -> &'static str {
+        match_casings!(self.type_casing => Sprite)
+    }
+
+    pub fn ident_sprite_state(&self) -> &'static str {
+        match_casings!(self.type_casing => SpriteState)
+    }
+
+    pub fn ident_theme(&self) -> &'static str {
+        match_casings!(self.type_casing => Theme)
+    }
+
+    pub fn ident_spritesheet(&self) -> &'static str {
+        match_casings!(self.field_casing => spritesheet)
+    }
+
+    pub fn ident_x(&self) -> &'static str {
+        match_casings!(self.field_casing => x)
+    }
+
+    pub fn ident_y(&self) -> &'static str {
+        match_casings!(self.field_casing => y)
+    }
+
+    pub fn ident_width(&self) -> &'static str {
+        match_casings!(self.field_casing => width)
+    }
+
+    pub fn ident_height(&self) -> &'static str {
+        match_casings!(self.field_casing => height)
+    }
+
+    pub fn ident_offset_x(&self) -> &'static str {
+        match_casings!(self.field_casing => offset_x)
+    }
+
+    pub fn ident_offset_y(&self) -> &'static str {
+        match_casings!(self.field_casing => offset_y)
+    }
+
+    pub fn ident_source_width(&self) -> &'static str {
+        match_casings!(self.field_casing => source_width)
+    }
+
+    pub fn ident_source_height(&self) -> &'static str {
+        match_casings!(self.field_casing => source_height)
+    }
+
+    pub fn ident_rotated(&self) -> &'static str {
+        match_casings!(self.field_casing => rotated)
+    }
+
+    pub fn ident_tags(&self) -> &'static str {
+        match_casings!(self.field_casing => tags)
+    }
+}
+
+/// A spritesheet with one trimmed, rotated and tagged sprite, for testing
+/// outputs.
+#[cfg(test)]
+pub(crate) fn test_spritesheet() -> crate::Spritesheet {
+    use crate::{Sprite, SpriteRect, Spritesheet, sources::SpriteOptions};
+
+    let close = Sprite {
+        pixmap_key: "icons0".to_string(),
+        rect: SpriteRect {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+        },
+        offset_x: 5,
+        offset_y: 6,
+        source_width: 7,
+        source_height: 8,
+        rotated: true,
+        options: SpriteOptions {
+            tags: vec!["ui".to_string(), r#"say "hi" \ bye"#.to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    Spritesheet {
+        sprites: [("close".to_string(), close)].into(),
+        trimmed: true,
+        allow_rotation: true,
+        tagged: true,
+        ..Default::default()
+    }
+}
+
+/// A spritesheet with a `hover-dark` state, which isn't an identifier in any
+/// language, on one of its two sprites, for testing outputs.
+#[cfg(test)]
+pub(crate) fn test_state_spritesheet() -> crate::Spritesheet {
+    use crate::{Sprite, SpriteRect, Spritesheet};
+
+    let sprite = |pixmap_key: &str, x| Sprite {
+        pixmap_key: pixmap_key.to_string(),
+        rect: SpriteRect {
+            x,
+            y: 0,
+            width: 2,
+            height: 2,
+        },
+        ..Default::default()
+    };
+
+    let mut close = sprite("icons0", 0);
+    close
+        .states
+        .insert("hover-dark".to_string(), sprite("icons1", 2));
+
+    Spritesheet {
+        sprites: [
+            ("close".to_string(), close),
+            ("open".to_string(), sprite("icons0", 4)),
+        ]
+        .into(),
+        states: vec!["hover-dark".to_string()],
+        ..Default::default()
+    }
+}
+
 mod tests {
     #[test]
     pub fn disclaimer_comments() {
@@ -41,4 +341,10 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    pub fn quoted_strings() {
+        assert_eq!(super::quote_string("close"), "\"close\"");
+        assert_eq!(super::quote_string("say \"hi\"\\\n"), r#""say \"hi\"\\\n""#);
+    }
 }
@@ -1,11 +1,12 @@
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
+use itertools::Itertools;
 use serde::Deserialize;
 use std::fs;
 
-use super::create_disclaimer_comment;
-use crate::{Spritesheet, match_casings, util::casings::Casing};
+use super::{TypeScriptTypes, create_disclaimer_comment, quote_string, typescript_property};
+use crate::{Sprite, Spritesheet, util::casings::Casing};
 
 #[derive(Default, Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -59,6 +60,7 @@ impl TypeScriptCodegenOutput {
         code.push('\n');
         code.push('\n');
 
+        let types = self.types();
         let mut indent = String::new();
 
         let mut footer = String::new();
@@ -94,8 +96,8 @@ impl TypeScriptCodegenOutput {
         if self.include_prelude_types {
             code.push_str(&indent);
             code.push_str(
-                &self
-                    .prelude_types()
+                &types
+                    .prelude_types(spritesheet)
                     .replace("\n", format!("\n{indent}").as_str()),
             );
             code.push('\n');
@@ -103,39 +105,57 @@ impl TypeScriptCodegenOutput {
         }
 
         let sprite_type = if self.include_prelude_types {
-            ": Sprite"
+            format!(": {}", types.ident_sprite())
         } else {
-            ""
+            String::new()
         };
 
-        macro_rules! push_field {
-            ($field:expr, $value:expr) => {
-                code.push_str(indent.as_str());
-                code.push('\t');
-                code.push_str($field);
-                code.push_str(": ");
-                code.push_str($value);
-                code.push(',');
-                code.push('\n');
-            };
+        for (key, sprite) in spritesheet.sprites.iter().sorted_by_key(|(key, _)| *key) {
+            code.push_str(indent.as_str());
+            code.push_str("export const ");
+            code.push_str(key);
+            code.push_str(&sprite_type);
+            code.push_str(" = {");
+            code.push('\n');
+            code.push_str(&self.sprite_fields(spritesheet, sprite, None, &format!("{indent}\t")));
+            code.push_str(indent.as_str());
+            code.push('}');
+            code.push(';');
+            code.push('\n');
         }
 
-        for (key, sprite) in &spritesheet.sprites {
+        let theme_type = if self.include_prelude_types {
+            format!(": {}", types.ident_theme())
+        } else {
+            String::new()
+        };
+
+        for theme in &spritesheet.themes {
             code.push_str(indent.as_str());
             code.push_str("export const ");
-            code.push_str(key);
-            code.push_str(sprite_type);
+            code.push_str(theme);
+            code.push_str(&theme_type);
             code.push_str(" = {");
             code.push('\n');
 
-            push_field!(
-                self.ident_spritesheet(),
-                format!("\"{}\"", sprite.pixmap_key).as_str()
-            );
-            push_field!(self.ident_x(), format!("{}", sprite.x).as_str());
-            push_field!(self.ident_y(), format!("{}", sprite.y).as_str());
-            push_field!(self.ident_width(), format!("{}", sprite.width).as_str());
-            push_field!(self.ident_height(), format!("{}", sprite.height).as_str());
+            for (key, sprite) in spritesheet.sprites.iter().sorted_by_key(|(key, _)| *key) {
+                code.push_str(indent.as_str());
+                code.push('\t');
+                code.push_str(&typescript_property(key));
+                code.push_str(": {");
+                code.push('\n');
+                code.push_str(&self.sprite_fields(
+                    spritesheet,
+                    sprite,
+                    Some(theme),
+                    &format!("{indent}\t\t"),
+                ));
+                code.push_str(indent.as_str());
+                code.push('\t');
+                code.push('}');
+                code.push(',');
+                code.push('\n');
+            }
 
             code.push_str(indent.as_str());
             code.push('}');
@@ -149,53 +169,245 @@ impl TypeScriptCodegenOutput {
         code
     }
 
-    pub fn prelude_types(&self) -> String {
-        format!(
-            "export interface {} {}",
-            self.ident_sprite(),
-            self.sprite_type()
-        )
-    }
+    /// The fields of a sprite's object literal, one per line at `indent`, on
+    /// the default pages or those of `theme`. States are nested objects.
+    pub fn sprite_fields(
+        &self,
+        spritesheet: &Spritesheet,
+        sprite: &Sprite,
+        theme: Option<&str>,
+        indent: &str,
+    ) -> String {
+        let types = self.types();
+        let mut code = String::new();
+        let pixmap_key = match theme {
+            Some(theme) => &sprite.theme_pixmap_keys[theme],
+            None => &sprite.pixmap_key,
+        };
 
-    pub fn sprite_type(&self) -> String {
-        let image = self.ident_spritesheet();
-        let x = self.ident_x();
-        let y = self.ident_y();
-        let width = self.ident_width();
-        let height = self.ident_height();
-
-        format!(
-            "{{\
-                \n\treadonly {image}: string,\
-                \n\treadonly {x}: number,\
-                \n\treadonly {y}: number,\
-                \n\treadonly {width}: number,\
-                \n\treadonly {height}: number,\
-            \n}}"
-        )
-    }
+        macro_rules! push_field {
+            ($field:expr, $value:expr) => {
+                code.push_str(indent);
+                code.push_str($field);
+                code.push_str(": ");
+                code.push_str($value);
+                code.push(',');
+                code.push('\n');
+            };
+        }
+
+        push_field!(types.ident_spritesheet(), quote_string(pixmap_key).as_str());
+        push_field!(types.ident_x(), format!("{}", sprite.rect.x).as_str());
+        push_field!(types.ident_y(), format!("{}", sprite.rect.y).as_str());
+        push_field!(
+            types.ident_width(),
+            format!("{}", sprite.rect.width).as_str()
+        );
+        push_field!(
+            types.ident_height(),
+            format!("{}", sprite.rect.height).as_str()
+        );
+
+        if spritesheet.trimmed {
+            push_field!(
+                types.ident_offset_x(),
+                format!("{}", sprite.offset_x).as_str()
+            );
+            push_field!(
+                types.ident_offset_y(),
+                format!("{}", sprite.offset_y).as_str()
+            );
+            push_field!(
+                types.ident_source_width(),
+                format!("{}", sprite.source_width).as_str()
+            );
+            push_field!(
+                types.ident_source_height(),
+                format!("{}", sprite.source_height).as_str()
+            );
+        }
+
+        if spritesheet.allow_rotation {
+            push_field!(
+                types.ident_rotated(),
+                format!("{}", sprite.rotated).as_str()
+            );
+        }
 
-    pub fn ident_sprite(&self) -> &'static str {
-        match_casings!(self.type_casing => Sprite)
+        if spritesheet.tagged {
+            let tags = sprite
+                .options
+                .tags
+                .iter()
+                .map(|tag| quote_string(tag))
+                .join(", ");
+            push_field!(types.ident_tags(), format!("[{tags}]").as_str());
+        }
+
+        for (state, state_sprite) in &sprite.states {
+            let state_fields =
+                self.sprite_fields(spritesheet, state_sprite, theme, &format!("{indent}\t"));
+            push_field!(
+                &typescript_property(state),
+                format!("{{\n{state_fields}{indent}}}").as_str()
+            );
+        }
+
+        code
     }
 
-    pub fn ident_spritesheet(&self) -> &'static str {
-        match_casings!(self.field_casing => spritesheet)
+    /// The sprite, state and theme types of this output.
+    pub fn types(&self) -> TypeScriptTypes<'_> {
+        TypeScriptTypes {
+            type_casing: &self.type_casing,
+            field_casing: &self.field_casing,
+            include_prelude_types: self.include_prelude_types,
+        }
     }
+}
 
-    pub fn ident_x(&self) -> &'static str {
-        match_casings!(self.field_casing => x)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outputs::codegen::{test_spritesheet, test_state_spritesheet};
+
+    #[test]
+    fn writes_trimmed_rotated_and_tagged_sprites() {
+        let output = TypeScriptCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Pascal,
+            field_casing: Casing::Camel,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            output.codegen("icons", &test_spritesheet()),
+            format!(
+                "{}\n\
+                \nexport interface Sprite {{\
+                \n\treadonly spritesheet: string,\
+                \n\treadonly x: number,\
+                \n\treadonly y: number,\
+                \n\treadonly width: number,\
+                \n\treadonly height: number,\
+                \n\treadonly offsetX: number,\
+                \n\treadonly offsetY: number,\
+                \n\treadonly sourceWidth: number,\
+                \n\treadonly sourceHeight: number,\
+                \n\treadonly rotated: boolean,\
+                \n\treadonly tags: readonly string[],\
+                \n}}\
+                \n\
+                \nexport const close: Sprite = {{\
+                \n\tspritesheet: \"icons0\",\
+                \n\tx: 1,\
+                \n\ty: 2,\
+                \n\twidth: 3,\
+                \n\theight: 4,\
+                \n\toffsetX: 5,\
+                \n\toffsetY: 6,\
+                \n\tsourceWidth: 7,\
+                \n\tsourceHeight: 8,\
+                \n\trotated: true,\
+                \n\ttags: [\"ui\", \"say \\\"hi\\\" \\\\ bye\"],\
+                \n}};\
+                \n\
+                \n",
+                create_disclaimer_comment("//")
+            )
+        );
     }
 
-    pub fn ident_y(&self) -> &'static str {
-        match_casings!(self.field_casing => y)
+    #[test]
+    fn names_types_in_their_casing() {
+        let output = TypeScriptCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Snake,
+            ..Default::default()
+        };
+
+        let code = output.codegen("icons", &test_spritesheet());
+        assert!(code.contains("export interface sprite {"));
+        assert!(code.contains("export const close: sprite = {"));
     }
 
-    pub fn ident_width(&self) -> &'static str {
-        match_casings!(self.field_casing => width)
+    #[test]
+    fn types_themes() {
+        let output = TypeScriptCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Pascal,
+            field_casing: Casing::Camel,
+            ..Default::default()
+        };
+
+        let mut spritesheet = test_spritesheet();
+        spritesheet.themes = vec!["dark".to_string()];
+        for sprite in spritesheet.sprites.values_mut() {
+            sprite.theme_pixmap_keys = [("dark".to_string(), "icons_dark0".to_string())].into();
+        }
+
+        let code = output.codegen("icons", &spritesheet);
+        assert!(code.contains("export interface Theme {\n\treadonly close: Sprite,\n}"));
+        assert!(code.contains(
+            "export const dark: Theme = {\n\tclose: {\n\t\tspritesheet: \"icons_dark0\","
+        ));
     }
 
-    pub fn ident_height(&self) -> &'static str {
-        match_casings!(self.field_casing => height)
+    #[test]
+    fn writes_states() {
+        let output = TypeScriptCodegenOutput {
+            include_prelude_types: true,
+            type_casing: Casing::Pascal,
+            field_casing: Casing::Camel,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            output.codegen("icons", &test_state_spritesheet()),
+            format!(
+                "{}\n\
+                \nexport interface SpriteState {{\
+                \n\treadonly spritesheet: string,\
+                \n\treadonly x: number,\
+                \n\treadonly y: number,\
+                \n\treadonly width: number,\
+                \n\treadonly height: number,\
+                \n}}\
+                \n\
+                \nexport interface Sprite {{\
+                \n\treadonly spritesheet: string,\
+                \n\treadonly x: number,\
+                \n\treadonly y: number,\
+                \n\treadonly width: number,\
+                \n\treadonly height: number,\
+                \n\treadonly \"hover-dark\"?: SpriteState,\
+                \n}}\
+                \n\
+                \nexport const close: Sprite = {{\
+                \n\tspritesheet: \"icons0\",\
+                \n\tx: 0,\
+                \n\ty: 0,\
+                \n\twidth: 2,\
+                \n\theight: 2,\
+                \n\t\"hover-dark\": {{\
+                \n\t\tspritesheet: \"icons1\",\
+                \n\t\tx: 2,\
+                \n\t\ty: 0,\
+                \n\t\twidth: 2,\
+                \n\t\theight: 2,\
+                \n\t}},\
+                \n}};\
+                \nexport const open: Sprite = {{\
+                \n\tspritesheet: \"icons0\",\
+                \n\tx: 4,\
+                \n\ty: 0,\
+                \n\twidth: 2,\
+                \n\theight: 2,\
+                \n}};\
+                \n\
+                \n",
+                create_disclaimer_comment("//")
+            )
+        );
     }
 }
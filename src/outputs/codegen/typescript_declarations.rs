@@ -1,11 +1,12 @@
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
+use itertools::Itertools;
 use serde::Deserialize;
 use std::fs;
 
-use super::create_disclaimer_comment;
-use crate::{Spritesheet, match_casings, util::casings::Casing};
+use super::{TypeScriptTypes, create_disclaimer_comment, typescript_property};
+use crate::{Spritesheet, util::casings::Casing};
 
 #[derive(Default, Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -38,6 +39,7 @@ impl TypeScriptDeclarationsCodegenOutput {
     }
 
     pub fn codegen(&self, name: &str, spritesheet: &Spritesheet) -> String {
+        let types = self.types();
         let mut code = String::new();
         code.push_str(&create_disclaimer_comment("//"));
         code.push('\n');
@@ -50,12 +52,17 @@ impl TypeScriptDeclarationsCodegenOutput {
 
         if self.include_prelude_types {
             code.push('\t');
-            code.push_str(self.prelude_types().replace("\n", "\n\t").as_str());
+            code.push_str(
+                types
+                    .prelude_types(spritesheet)
+                    .replace("\n", "\n\t")
+                    .as_str(),
+            );
             code.push('\n');
         }
 
-        let sprite_type = self.sprite_type();
-        let ident_sprite = self.ident_sprite();
+        let sprite_type = types.sprite_type(spritesheet);
+        let ident_sprite = types.ident_sprite();
         let indented_sprite_type = sprite_type.replace("\n", "\n\t");
         let real_sprite_type = if self.include_prelude_types {
             ident_sprite
@@ -63,7 +70,7 @@ impl TypeScriptDeclarationsCodegenOutput {
             indented_sprite_type.as_str()
         };
 
-        for key in spritesheet.sprites.keys() {
+        for key in spritesheet.sprites.keys().sorted() {
             code.push('\t');
             code.push_str("export const ");
             code.push_str(key.as_str());
@@ -74,6 +81,38 @@ impl TypeScriptDeclarationsCodegenOutput {
             code.push('\n');
         }
 
+        let theme_sprite_type = real_sprite_type.replace("\n", "\n\t");
+        for theme in &spritesheet.themes {
+            code.push('\t');
+            code.push_str("export const ");
+            code.push_str(theme);
+
+            if self.include_prelude_types {
+                code.push_str(": ");
+                code.push_str(types.ident_theme());
+                code.push(';');
+                code.push('\n');
+                continue;
+            }
+
+            code.push_str(": {");
+            code.push('\n');
+
+            for key in spritesheet.sprites.keys().sorted() {
+                code.push_str("\t\treadonly ");
+                code.push_str(&typescript_property(key));
+                code.push_str(": ");
+                code.push_str(&theme_sprite_type);
+                code.push(';');
+                code.push('\n');
+            }
+
+            code.push('\t');
+            code.push('}');
+            code.push(';');
+            code.push('\n');
+        }
+
         code.push('}');
         code.push('\n');
         code.push('\n');
@@ -89,53 +128,54 @@ impl TypeScriptDeclarationsCodegenOutput {
         code
     }
 
-    pub fn prelude_types(&self) -> String {
-        format!(
-            "export interface {} {}",
-            self.ident_sprite(),
-            self.sprite_type()
-        )
-    }
-
-    pub fn sprite_type(&self) -> String {
-        let image = self.ident_spritesheet();
-        let x = self.ident_x();
-        let y = self.ident_y();
-        let width = self.ident_width();
-        let height = self.ident_height();
-
-        format!(
-            "{{\
-                \n\treadonly {image}: string,\
-                \n\treadonly {x}: number,\
-                \n\treadonly {y}: number,\
-                \n\treadonly {width}: number,\
-                \n\treadonly {height}: number,\
-            \n}}"
-        )
-    }
-
-    pub fn ident_sprite(&self) -> &'static str {
-        match_casings!(self.type_casing => Sprite)
-    }
-
-    pub fn ident_spritesheet(&self) -> &'static str {
-        match_casings!(self.field_casing => spritesheet)
-    }
-
-    pub fn ident_x(&self) -> &'static str {
-        match_casings!(self.field_casing => x)
-    }
-
-    pub fn ident_y(&self) -> &'static str {
-        match_casings!(self.field_casing => y)
+    /// The sprite, state and theme types of this output.
+    pub fn types(&self) -> TypeScriptTypes<'_> {
+        TypeScriptTypes {
+            type_casing: &self.type_casing,
+            field_casing: &self.field_casing,
+            include_prelude_types: self.include_prelude_types,
+        }
     }
+}
 
-    pub fn ident_width(&self) -> &'static str {
-        match_casings!(self.field_casing => width)
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outputs::codegen::test_spritesheet;
+
+    #[test]
+    fn declares_trimmed_rotated_and_tagged_sprites() {
+        let output = TypeScriptDeclarationsCodegenOutput {
+            field_casing: Casing::Camel,
+            type_casing: Casing::Pascal,
+            ..Default::default()
+        };
 
-    pub fn ident_height(&self) -> &'static str {
-        match_casings!(self.field_casing => height)
+        assert_eq!(
+            output.codegen("icons", &test_spritesheet()),
+            format!(
+                "{}\n\
+                \ndeclare namespace icons {{\
+                \n\texport const close: {{\
+                \n\t\treadonly spritesheet: string,\
+                \n\t\treadonly x: number,\
+                \n\t\treadonly y: number,\
+                \n\t\treadonly width: number,\
+                \n\t\treadonly height: number,\
+                \n\t\treadonly offsetX: number,\
+                \n\t\treadonly offsetY: number,\
+                \n\t\treadonly sourceWidth: number,\
+                \n\t\treadonly sourceHeight: number,\
+                \n\t\treadonly rotated: boolean,\
+                \n\t\treadonly tags: readonly string[],\
+                \n\t}};\
+                \n}}\
+                \n\
+                \nexport = icons;\
+                \nexport as namespace icons;\
+                \n",
+                create_disclaimer_comment("//")
+            )
+        );
     }
 }
@@ -1,10 +1,12 @@
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
+use image::{ExtendedColorType, ImageEncoder, codecs::png::PngEncoder};
 use serde::Deserialize;
 use std::fs;
+use tiny_skia::Pixmap;
 
-use crate::Spritesheet;
+use crate::{Spritesheet, processing::alpha_bleed::alpha_bleed};
 
 #[derive(Default, Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -44,9 +46,12 @@ impl DirOutput {
                 self.file_extension.as_extension()
             ));
 
-            let png_encoded = pixmap
-                .encode_png()
-                .context("failed to encode spritesheet as png")?;
+            let png_encoded = if spritesheet.alpha_bleed {
+                encode_alpha_bled_png(pixmap)
+            } else {
+                pixmap.encode_png().map_err(Into::into)
+            }
+            .context("failed to encode spritesheet as png")?;
 
             let contents = match self.file_extension {
                 FileExtension::Png => png_encoded,
@@ -58,3 +63,28 @@ impl DirOutput {
         Ok(())
     }
 }
+
+/// Pixmaps are premultiplied, which zeroes the colour of transparent pixels, so
+/// bleeding has to happen on the straight pixels just before encoding.
+fn encode_alpha_bled_png(pixmap: &Pixmap) -> Result<Vec<u8>> {
+    let mut pixels: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let pixel = pixel.demultiply();
+            [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+        })
+        .collect();
+
+    alpha_bleed(&mut pixels, pixmap.width(), pixmap.height());
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(
+        &pixels,
+        pixmap.width(),
+        pixmap.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+
+    Ok(png)
+}
@@ -0,0 +1,57 @@
+use super::PackedRect;
+
+/// Lays sprites out in rows, in order. A row is as tall as its tallest sprite,
+/// and a new page is started once a row would overflow the bottom edge.
+pub fn pack(sizes: &[(u32, u32)], page_width: u32, page_height: u32) -> Vec<PackedRect> {
+    let mut rects = Vec::with_capacity(sizes.len());
+
+    let mut page = 0;
+    let mut current_x = 0;
+    let mut current_y = 0;
+    let mut highest_in_row = 0;
+
+    for &(width, height) in sizes {
+        if current_x + width > page_width {
+            current_x = 0;
+            current_y += highest_in_row;
+            highest_in_row = 0;
+        }
+
+        if current_y + height > page_height {
+            page += 1;
+            current_x = 0;
+            current_y = 0;
+            highest_in_row = 0;
+        }
+
+        rects.push(PackedRect {
+            page,
+            x: current_x,
+            y: current_y,
+            width,
+            height,
+            rotated: false,
+        });
+
+        current_x += width;
+        highest_in_row = highest_in_row.max(height);
+    }
+
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_rows_and_pages() {
+        let rects = pack(&[(10, 10); 5], 20, 20);
+        let positions: Vec<_> = rects.iter().map(|r| (r.page, r.x, r.y)).collect();
+
+        assert_eq!(
+            positions,
+            vec![(0, 0, 0), (0, 10, 0), (0, 0, 10), (0, 10, 10), (1, 0, 0)]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+// https://github.com/juj/RectangleBinPack/blob/master/MaxRectsBinPack.cpp
+
+use serde::Deserialize;
+
+use super::PackedRect;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MaxRectsHeuristic {
+    /// Picks the free rect where the shorter leftover side is smallest.
+    #[default]
+    BestShortSideFit,
+    /// Picks the smallest free rect the sprite fits in.
+    BestAreaFit,
+    /// Picks the free rect that keeps the sprite closest to the bottom left,
+    /// Tetris style.
+    BottomLeft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        other.x < self.right()
+            && other.right() > self.x
+            && other.y < self.bottom()
+            && other.bottom() > self.y
+    }
+}
+
+struct Bin {
+    free: Vec<Rect>,
+}
+
+impl Bin {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            free: vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+        }
+    }
+
+    /// Finds the best free spot for a sprite along with its score, where lower
+    /// scores are better, and whether it had to be rotated to get there.
+    fn find(
+        &self,
+        width: u32,
+        height: u32,
+        heuristic: MaxRectsHeuristic,
+        allow_rotation: bool,
+    ) -> Option<(Rect, (u64, u64), bool)> {
+        let upright = self.find_upright(width, height, heuristic);
+
+        if !allow_rotation || width == height {
+            return upright.map(|(rect, score)| (rect, score, false));
+        }
+
+        match (upright, self.find_upright(height, width, heuristic)) {
+            (Some((rect, score)), Some((_, rotated_score))) if score <= rotated_score => {
+                Some((rect, score, false))
+            }
+            (_, Some((rect, score))) => Some((rect, score, true)),
+            (Some((rect, score)), None) => Some((rect, score, false)),
+            (None, None) => None,
+        }
+    }
+
+    fn find_upright(
+        &self,
+        width: u32,
+        height: u32,
+        heuristic: MaxRectsHeuristic,
+    ) -> Option<(Rect, (u64, u64))> {
+        let mut best: Option<(Rect, (u64, u64))> = None;
+
+        for free in &self.free {
+            if free.width < width || free.height < height {
+                continue;
+            }
+
+            let leftover_x = (free.width - width) as u64;
+            let leftover_y = (free.height - height) as u64;
+            let short_side = leftover_x.min(leftover_y);
+            let long_side = leftover_x.max(leftover_y);
+
+            let score = match heuristic {
+                MaxRectsHeuristic::BestShortSideFit => (short_side, long_side),
+                MaxRectsHeuristic::BestAreaFit => (
+                    free.width as u64 * free.height as u64 - width as u64 * height as u64,
+                    short_side,
+                ),
+                MaxRectsHeuristic::BottomLeft => ((free.y + height) as u64, free.x as u64),
+            };
+
+            if best.is_none_or(|(_, best_score)| score < best_score) {
+                best = Some((
+                    Rect {
+                        x: free.x,
+                        y: free.y,
+                        width,
+                        height,
+                    },
+                    score,
+                ));
+            }
+        }
+
+        best
+    }
+
+    fn place(&mut self, used: Rect) {
+        let mut split = Vec::new();
+
+        self.free.retain(|free| {
+            if !free.intersects(&used) {
+                return true;
+            }
+
+            if used.x > free.x {
+                split.push(Rect {
+                    width: used.x - free.x,
+                    ..*free
+                });
+            }
+
+            if used.right() < free.right() {
+                split.push(Rect {
+                    x: used.right(),
+                    width: free.right() - used.right(),
+                    ..*free
+                });
+            }
+
+            if used.y > free.y {
+                split.push(Rect {
+                    height: used.y - free.y,
+                    ..*free
+                });
+            }
+
+            if used.bottom() < free.bottom() {
+                split.push(Rect {
+                    y: used.bottom(),
+                    height: free.bottom() - used.bottom(),
+                    ..*free
+                });
+            }
+
+            false
+        });
+
+        self.free.extend(split);
+        self.prune();
+    }
+
+    /// Removes free rects that are entirely inside another free rect.
+    fn prune(&mut self) {
+        let mut index = 0;
+
+        while index < self.free.len() {
+            let current = self.free[index];
+            let redundant = self.free.iter().enumerate().any(|(other_index, other)| {
+                other_index != index
+                    && other.contains(&current)
+                    && (other != &current || other_index < index)
+            });
+
+            if redundant {
+                self.free.swap_remove(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+/// Packs sprites largest first, opening a new page whenever a sprite doesn't
+/// fit on any of the existing ones.
+pub fn pack(
+    sizes: &[(u32, u32)],
+    page_width: u32,
+    page_height: u32,
+    heuristic: MaxRectsHeuristic,
+    allow_rotation: bool,
+) -> Vec<PackedRect> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| {
+        let (width, height) = sizes[index];
+        std::cmp::Reverse((width.max(height), width.min(height)))
+    });
+
+    let mut bins: Vec<Bin> = Vec::new();
+    let mut rects = vec![PackedRect::default(); sizes.len()];
+
+    for index in order {
+        let (width, height) = sizes[index];
+
+        let found = bins.iter().enumerate().find_map(|(page, bin)| {
+            bin.find(width, height, heuristic, allow_rotation)
+                .map(|(rect, _, rotated)| (page, rect, rotated))
+        });
+
+        let (page, rect, rotated) = match found {
+            Some(found) => found,
+            None => {
+                let bin = Bin::new(page_width, page_height);
+                let (rect, _, rotated) = bin
+                    .find(width, height, heuristic, allow_rotation)
+                    .expect("sprite should fit on an empty page");
+                bins.push(bin);
+                (bins.len() - 1, rect, rotated)
+            }
+        };
+
+        bins[page].place(rect);
+        rects[index] = PackedRect {
+            page,
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+            rotated,
+        };
+    }
+
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(lhs: &PackedRect, rhs: &PackedRect) -> bool {
+        lhs.page == rhs.page
+            && lhs.x < rhs.x + rhs.width
+            && rhs.x < lhs.x + lhs.width
+            && lhs.y < rhs.y + rhs.height
+            && rhs.y < lhs.y + lhs.height
+    }
+
+    #[test]
+    fn packs_without_overlapping() {
+        let sizes = [
+            (30, 10),
+            (10, 30),
+            (20, 20),
+            (5, 5),
+            (40, 8),
+            (8, 40),
+            (12, 7),
+        ];
+
+        for heuristic in [
+            MaxRectsHeuristic::BestShortSideFit,
+            MaxRectsHeuristic::BestAreaFit,
+            MaxRectsHeuristic::BottomLeft,
+        ] {
+            let rects = pack(&sizes, 64, 64, heuristic, false);
+
+            for (index, rect) in rects.iter().enumerate() {
+                assert_eq!((rect.width, rect.height), sizes[index]);
+                assert!(rect.x + rect.width <= 64 && rect.y + rect.height <= 64);
+
+                for other in &rects[index + 1..] {
+                    assert!(!overlaps(rect, other), "{rect:?} overlaps {other:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fills_pages_before_opening_new_ones() {
+        let rects = pack(&[(16, 16); 17], 64, 64, MaxRectsHeuristic::default(), false);
+
+        assert_eq!(rects.iter().filter(|rect| rect.page == 0).count(), 16);
+        assert_eq!(rects.iter().filter(|rect| rect.page == 1).count(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+use anyhow::{Result, bail};
+use itertools::Itertools;
+use serde::Deserialize;
+
+pub mod grid;
+pub mod max_rects;
+
+pub use self::max_rects::MaxRectsHeuristic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Packer {
+    /// Places sprites left to right in rows, wrapping onto a new row when the
+    /// current one is full. Every sprite gets the same cell when sprites are
+    /// the same size.
+    Grid,
+    /// Packs sprites as tightly as possible with the MaxRects algorithm.
+    MaxRects,
+}
+
+/// Options shared by every packer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackOptions {
+    /// Empty pixels between neighbouring sprites.
+    pub padding: u32,
+    /// Only used by the `max_rects` packer.
+    pub heuristic: MaxRectsHeuristic,
+    /// Lets the `max_rects` packer rotate sprites 90 degrees clockwise when it
+    /// saves space.
+    pub allow_rotation: bool,
+}
+
+/// Where a sprite ended up after packing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedRect {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    /// The width on the page, which is the sprite's height when rotated.
+    pub width: u32,
+    /// The height on the page, which is the sprite's width when rotated.
+    pub height: u32,
+    /// Whether the sprite was rotated 90 degrees clockwise.
+    pub rotated: bool,
+}
+
+impl Packer {
+    /// Whether a sprite of `size` fits on an empty page, rotating it if the
+    /// packer is allowed to.
+    pub fn fits(
+        &self,
+        (width, height): (u32, u32),
+        page_width: u32,
+        page_height: u32,
+        options: &PackOptions,
+    ) -> bool {
+        let rotates = options.allow_rotation && *self == Self::MaxRects;
+
+        (width <= page_width && height <= page_height)
+            || (rotates && height <= page_width && width <= page_height)
+    }
+
+    /// Packs every size into as many `page_width` by `page_height` pages as
+    /// needed. The returned rects are in the same order as `sizes`.
+    pub fn pack(
+        &self,
+        sizes: &[(u32, u32)],
+        page_width: u32,
+        page_height: u32,
+        options: &PackOptions,
+    ) -> Result<Vec<PackedRect>> {
+        let padding = options.padding;
+
+        for (index, &(width, height)) in sizes.iter().enumerate() {
+            if !self.fits((width, height), page_width, page_height, options) {
+                bail!(
+                    "sprite #{index} ({width}x{height}) does not fit in a {page_width}x{page_height} spritesheet"
+                );
+            }
+        }
+
+        // Padding only has to separate sprites, so the page grows by the same
+        // amount to let it hang off the right and bottom edges.
+        let padded_sizes: Vec<(u32, u32)> = sizes
+            .iter()
+            .map(|(width, height)| (width + padding, height + padding))
+            .collect();
+        let page_width = page_width + padding;
+        let page_height = page_height + padding;
+
+        let mut rects = match self {
+            Self::Grid => self::grid::pack(&padded_sizes, page_width, page_height),
+            Self::MaxRects => self::max_rects::pack(
+                &padded_sizes,
+                page_width,
+                page_height,
+                options.heuristic,
+                options.allow_rotation,
+            ),
+        };
+
+        for rect in &mut rects {
+            rect.width -= padding;
+            rect.height -= padding;
+        }
+
+        Ok(rects)
+    }
+
+    /// Finds the smallest page that fits every size on a single page, growing
+    /// up to `max_size` on each side. Sizes that still don't fit at `max_size`
+    /// are spread over as many `max_size` pages as needed.
+    pub fn pack_smallest(
+        &self,
+        sizes: &[(u32, u32)],
+        max_size: Option<u32>,
+        power_of_two: bool,
+        options: &PackOptions,
+    ) -> Result<(u32, u32, Vec<PackedRect>)> {
+        let padding = options.padding;
+
+        let max_size = match (max_size, power_of_two) {
+            (Some(max_size), true) => Some(prev_power_of_two(max_size)),
+            (max_size, _) => max_size,
+        };
+
+        // Rotated sprites only need their shorter side to fit either way.
+        let rotates = options.allow_rotation && *self == Self::MaxRects;
+        let min_side = |width: u32, height: u32, side: u32| match rotates {
+            true => width.min(height),
+            false => side,
+        };
+
+        let min_width = sizes
+            .iter()
+            .map(|&(width, height)| min_side(width, height, width))
+            .max()
+            .unwrap_or(1);
+        let min_height = sizes
+            .iter()
+            .map(|&(width, height)| min_side(width, height, height))
+            .max()
+            .unwrap_or(1);
+        let longest_side = sizes
+            .iter()
+            .map(|&(width, height)| width.max(height))
+            .max()
+            .unwrap_or(1);
+        let area: u64 = sizes
+            .iter()
+            .map(|(width, height)| (width + padding) as u64 * (height + padding) as u64)
+            .sum();
+
+        let mut side = longest_side.max((area as f64).sqrt().ceil() as u32).max(1);
+        if power_of_two {
+            side = side.next_power_of_two();
+        }
+
+        if let Some(max_size) = max_size
+            && side > max_size
+        {
+            return Ok((
+                max_size,
+                max_size,
+                self.pack(sizes, max_size, max_size, options)?,
+            ));
+        }
+
+        let fits = |width: u32, height: u32| -> Result<Option<Vec<PackedRect>>> {
+            if !sizes
+                .iter()
+                .all(|&size| self.fits(size, width, height, options))
+            {
+                return Ok(None);
+            }
+
+            let rects = self.pack(sizes, width, height, options)?;
+            Ok(rects.iter().all(|rect| rect.page == 0).then_some(rects))
+        };
+
+        // Grow until everything fits on one page, or give up at `max_size`.
+        let mut lower = side;
+        let (mut width, mut height, mut rects) = loop {
+            if let Some(rects) = fits(side, side)? {
+                break (side, side, rects);
+            }
+
+            lower = side + 1;
+            side = match max_size {
+                Some(max_size) if side >= max_size => {
+                    return Ok((
+                        max_size,
+                        max_size,
+                        self.pack(sizes, max_size, max_size, options)?,
+                    ));
+                }
+                Some(max_size) => side.saturating_mul(2).min(max_size),
+                None => side.saturating_mul(2),
+            };
+        };
+
+        if power_of_two {
+            // Every smaller power of two page, smallest first and wider before
+            // taller, so tall sprites can narrow the page as well as wide ones
+            // can shorten it.
+            let powers = |min: u32| {
+                std::iter::successors(Some(min.max(1).next_power_of_two()), |side| {
+                    side.checked_mul(2)
+                })
+                .take_while(|side| *side <= width)
+            };
+            let candidates = powers(min_width)
+                .flat_map(|width| powers(min_height).map(move |height| (width, height)))
+                .filter(|&(candidate_width, candidate_height)| {
+                    (candidate_width, candidate_height) != (width, height)
+                })
+                .sorted_by_key(|&(width, height)| (width as u64 * height as u64, height));
+
+            for (candidate_width, candidate_height) in candidates {
+                if let Some(smaller) = fits(candidate_width, candidate_height)? {
+                    return Ok((candidate_width, candidate_height, smaller));
+                }
+            }
+
+            return Ok((width, height, rects));
+        }
+
+        // Narrow down the smallest square, then trim its height and width.
+        let mut upper = width;
+        while lower < upper {
+            let middle = lower + (upper - lower) / 2;
+            match fits(middle, middle)? {
+                Some(smaller) => {
+                    upper = middle;
+                    (width, height, rects) = (middle, middle, smaller);
+                }
+                None => lower = middle + 1,
+            }
+        }
+
+        let mut lower = min_height;
+        let mut upper = height;
+        while lower < upper {
+            let middle = lower + (upper - lower) / 2;
+            match fits(width, middle)? {
+                Some(smaller) => {
+                    upper = middle;
+                    (height, rects) = (middle, smaller);
+                }
+                None => lower = middle + 1,
+            }
+        }
+
+        let mut lower = min_width;
+        let mut upper = width;
+        while lower < upper {
+            let middle = lower + (upper - lower) / 2;
+            match fits(middle, height)? {
+                Some(smaller) => {
+                    upper = middle;
+                    (width, rects) = (middle, smaller);
+                }
+                None => lower = middle + 1,
+            }
+        }
+
+        Ok((width, height, rects))
+    }
+}
+
+/// The largest power of two that's no larger than `value`, or `0` for `0`.
+pub fn prev_power_of_two(value: u32) -> u32 {
+    if value == 0 {
+        0
+    } else {
+        1 << (31 - value.leading_zeros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_smallest_page() {
+        let packer = Packer::MaxRects;
+        let options = PackOptions::default();
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(16, 16); 4], None, false, &options)
+            .unwrap();
+        assert_eq!((width, height), (32, 32));
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(16, 16); 2], None, true, &options)
+            .unwrap();
+        assert_eq!((width, height), (32, 16));
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(20, 10); 3], None, false, &options)
+            .unwrap();
+        assert_eq!((width, height), (20, 30));
+    }
+
+    #[test]
+    fn narrows_power_of_two_pages_for_tall_sprites() {
+        let packer = Packer::MaxRects;
+        let options = PackOptions::default();
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(10, 100)], None, true, &options)
+            .unwrap();
+        assert_eq!((width, height), (16, 128));
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(100, 10)], None, true, &options)
+            .unwrap();
+        assert_eq!((width, height), (128, 16));
+
+        let (width, height, _) = packer
+            .pack_smallest(&[(16, 128); 2], None, true, &options)
+            .unwrap();
+        assert_eq!((width, height), (32, 128));
+    }
+
+    #[test]
+    fn spills_onto_more_pages_at_max_size() {
+        let (width, height, rects) = Packer::MaxRects
+            .pack_smallest(&[(16, 16); 5], Some(40), true, &PackOptions::default())
+            .unwrap();
+
+        assert_eq!((width, height), (32, 32));
+        assert_eq!(rects.iter().map(|rect| rect.page).max(), Some(1));
+    }
+
+    #[test]
+    fn pads_between_sprites_but_not_past_edges() {
+        let rects = Packer::Grid
+            .pack(
+                &[(10, 10); 4],
+                22,
+                22,
+                &PackOptions {
+                    padding: 2,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let positions: Vec<_> = rects.iter().map(|r| (r.page, r.x, r.y)).collect();
+
+        assert_eq!(
+            positions,
+            vec![(0, 0, 0), (0, 12, 0), (0, 0, 12), (0, 12, 12)]
+        );
+        assert!(
+            rects
+                .iter()
+                .all(|rect| rect.width == 10 && rect.height == 10)
+        );
+    }
+
+    #[test]
+    fn rotates_sprites_only_when_allowed() {
+        let options = PackOptions {
+            allow_rotation: true,
+            ..Default::default()
+        };
+
+        let rects = Packer::MaxRects
+            .pack(&[(10, 30)], 30, 10, &options)
+            .unwrap();
+        assert_eq!(
+            (rects[0].width, rects[0].height, rects[0].rotated),
+            (30, 10, true)
+        );
+
+        assert!(
+            Packer::MaxRects
+                .pack(&[(10, 30)], 30, 10, &PackOptions::default())
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,126 @@
+/// Fills the colour of every fully transparent pixel with the average colour of
+/// its nearest visible neighbours, spreading outward until the whole image is
+/// covered. Alpha is left untouched, so the image looks the same but doesn't
+/// darken at the edges when it's filtered.
+///
+/// `pixels` is straight (not premultiplied) RGBA with 4 bytes per pixel, since
+/// premultiplied transparent pixels can't hold a colour.
+pub fn alpha_bleed(pixels: &mut [u8], width: u32, height: u32) {
+    let (width, height) = (width as usize, height as usize);
+    debug_assert_eq!(pixels.len(), width * height * 4);
+
+    let mut filled: Vec<bool> = pixels.chunks_exact(4).map(|pixel| pixel[3] > 0).collect();
+    let mut queued = filled.clone();
+
+    let mut frontier: Vec<usize> = Vec::new();
+    for index in 0..width * height {
+        if !filled[index] && neighbours(index, width, height).any(|other| filled[other]) {
+            queued[index] = true;
+            frontier.push(index);
+        }
+    }
+
+    while !frontier.is_empty() {
+        // Every pixel in this layer only averages pixels from earlier layers,
+        // so the result doesn't depend on iteration order.
+        let colours: Vec<[u8; 3]> = frontier
+            .iter()
+            .map(|&index| {
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+
+                for other in neighbours(index, width, height).filter(|&other| filled[other]) {
+                    for channel in 0..3 {
+                        sum[channel] += pixels[other * 4 + channel] as u32;
+                    }
+                    count += 1;
+                }
+
+                sum.map(|channel| ((channel + count / 2) / count) as u8)
+            })
+            .collect();
+
+        for (&index, colour) in frontier.iter().zip(colours) {
+            pixels[index * 4..index * 4 + 3].copy_from_slice(&colour);
+            filled[index] = true;
+        }
+
+        let mut next = Vec::new();
+        for &index in &frontier {
+            for other in neighbours(index, width, height) {
+                if !queued[other] {
+                    queued[other] = true;
+                    next.push(other);
+                }
+            }
+        }
+
+        frontier = next;
+    }
+}
+
+fn neighbours(index: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((index % width) as isize, (index / width) as isize);
+
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(nx, ny)| {
+            (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width as isize && ny < height as isize
+        })
+        .map(move |(nx, ny)| ny as usize * width + nx as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn fixture(pixels: &[[u8; 4]]) -> Vec<u8> {
+        pixels.concat()
+    }
+
+    #[test]
+    fn bleeds_colour_into_transparent_pixels() {
+        let mut pixels = fixture(&[
+            CLEAR, CLEAR, CLEAR, //
+            CLEAR, RED, CLEAR, //
+            CLEAR, CLEAR, CLEAR, //
+        ]);
+        alpha_bleed(&mut pixels, 3, 3);
+
+        for (index, pixel) in pixels.chunks_exact(4).enumerate() {
+            let alpha = if index == 4 { 255 } else { 0 };
+            assert_eq!(pixel, [255, 0, 0, alpha], "pixel {index}");
+        }
+    }
+
+    #[test]
+    fn spreads_past_immediate_neighbours() {
+        let mut pixels = fixture(&[RED, CLEAR, CLEAR, CLEAR]);
+        alpha_bleed(&mut pixels, 4, 1);
+
+        assert_eq!(
+            pixels,
+            fixture(&[RED, [255, 0, 0, 0], [255, 0, 0, 0], [255, 0, 0, 0]])
+        );
+    }
+
+    #[test]
+    fn averages_nearest_neighbours() {
+        let mut pixels = fixture(&[RED, CLEAR, BLUE]);
+        alpha_bleed(&mut pixels, 3, 1);
+
+        assert_eq!(&pixels[4..8], [128, 0, 128, 0]);
+    }
+
+    #[test]
+    fn leaves_fully_transparent_images_alone() {
+        let mut pixels = fixture(&[CLEAR; 4]);
+        alpha_bleed(&mut pixels, 2, 2);
+
+        assert_eq!(pixels, fixture(&[CLEAR; 4]));
+    }
+}
@@ -0,0 +1,76 @@
+use tiny_skia::Pixmap;
+
+/// Repeats the border pixels of the `width` by `height` area at `x`, `y`
+/// outward by `amount` pixels, so filtering at the edges of a sprite samples
+/// its own colours instead of its neighbours'. The extruded area must fit
+/// inside the pixmap.
+pub fn extrude(pixmap: &mut Pixmap, x: u32, y: u32, width: u32, height: u32, amount: u32) {
+    if amount == 0 || width == 0 || height == 0 {
+        return;
+    }
+
+    let stride = pixmap.width() as usize;
+    let pixels = pixmap.pixels_mut();
+    let (x, y, width, height, amount) = (
+        x as usize,
+        y as usize,
+        width as usize,
+        height as usize,
+        amount as usize,
+    );
+
+    for row in y..y + height {
+        let left = pixels[row * stride + x];
+        let right = pixels[row * stride + x + width - 1];
+
+        pixels[row * stride + x - amount..row * stride + x].fill(left);
+        pixels[row * stride + x + width..row * stride + x + width + amount].fill(right);
+    }
+
+    let left = x - amount;
+    let right = x + width + amount;
+
+    for offset in 1..=amount {
+        pixels.copy_within(
+            y * stride + left..y * stride + right,
+            (y - offset) * stride + left,
+        );
+
+        let bottom = y + height - 1;
+        pixels.copy_within(
+            bottom * stride + left..bottom * stride + right,
+            (bottom + offset) * stride + left,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::{Color, PremultipliedColorU8};
+
+    #[test]
+    fn extrudes_edges_and_corners() {
+        let mut pixmap = Pixmap::new(6, 6).unwrap();
+        let mut red = Pixmap::new(2, 2).unwrap();
+        red.fill(Color::from_rgba8(255, 0, 0, 255));
+        pixmap.draw_pixmap(
+            2,
+            2,
+            red.as_ref(),
+            &Default::default(),
+            Default::default(),
+            None,
+        );
+
+        extrude(&mut pixmap, 2, 2, 2, 2, 1);
+
+        let red = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+        for y in 0..6 {
+            for x in 0..6 {
+                let expected = (1..5).contains(&x) && (1..5).contains(&y);
+                assert_eq!(pixmap.pixel(x, y).unwrap() == red, expected, "({x}, {y})");
+            }
+        }
+    }
+}
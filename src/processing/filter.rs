@@ -0,0 +1,228 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::bail;
+use serde::Deserialize;
+use tiny_skia::{Color, Pixmap};
+
+/// Image filters run on a sprite to make one of its states, such as a
+/// greyed out `disabled` state.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "StateFilterConfig"))]
+pub struct StateFilter {
+    /// How far to move colours towards grey, from `0` to `1`.
+    pub desaturate: f32,
+    /// Multiplies every colour, so `1.2` brightens and `0.8` darkens.
+    pub brightness: f32,
+    /// Multiplies the alpha of every pixel, from `0` to `1`.
+    pub opacity: f32,
+}
+
+impl Default for StateFilter {
+    fn default() -> Self {
+        Self {
+            desaturate: 0.0,
+            brightness: 1.0,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl StateFilter {
+    /// Checks every value is in its range.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(0.0..=1.0).contains(&self.desaturate) {
+            bail!("desaturate should be from 0 to 1, not {}", self.desaturate);
+        }
+
+        if !(self.brightness.is_finite() && self.brightness >= 0.0) {
+            bail!("brightness should be 0 or more, not {}", self.brightness);
+        }
+
+        if !(0.0..=1.0).contains(&self.opacity) {
+            bail!("opacity should be from 0 to 1, not {}", self.opacity);
+        }
+
+        Ok(())
+    }
+
+    /// The values compared and hashed, with `-0.0` counted as `0.0`.
+    fn normalized_bits(&self) -> [u32; 3] {
+        [self.desaturate, self.brightness, self.opacity].map(|value| (value + 0.0).to_bits())
+    }
+}
+
+// Filters are part of the key identical sprites are deduplicated by, so they
+// need to be hashable even though they hold floats. Comparing the same bits
+// that are hashed keeps `Eq` reflexive, even for NaN.
+impl PartialEq for StateFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized_bits() == other.normalized_bits()
+    }
+}
+
+impl Eq for StateFilter {}
+
+impl Hash for StateFilter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized_bits().hash(state);
+    }
+}
+
+/// A `StateFilter` as written in a config, before it's checked.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(default)]
+struct StateFilterConfig {
+    desaturate: f32,
+    brightness: f32,
+    opacity: f32,
+}
+
+#[cfg(feature = "serde")]
+impl Default for StateFilterConfig {
+    fn default() -> Self {
+        let StateFilter {
+            desaturate,
+            brightness,
+            opacity,
+        } = StateFilter::default();
+
+        Self {
+            desaturate,
+            brightness,
+            opacity,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<StateFilterConfig> for StateFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(config: StateFilterConfig) -> Result<Self, Self::Error> {
+        let filter = Self {
+            desaturate: config.desaturate,
+            brightness: config.brightness,
+            opacity: config.opacity,
+        };
+        filter.validate()?;
+
+        Ok(filter)
+    }
+}
+
+/// Desaturates, brightens and fades every pixel of a pixmap.
+pub fn filter(pixmap: &mut Pixmap, filter: &StateFilter) {
+    let desaturate = filter.desaturate.clamp(0.0, 1.0);
+    let brightness = filter.brightness.max(0.0);
+    let opacity = filter.opacity.clamp(0.0, 1.0);
+
+    for pixel in pixmap.pixels_mut() {
+        if pixel.alpha() == 0 {
+            continue;
+        }
+
+        let straight = pixel.demultiply();
+        let (red, green, blue) = (
+            straight.red() as f32 / 255.0,
+            straight.green() as f32 / 255.0,
+            straight.blue() as f32 / 255.0,
+        );
+        let luma = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+        let channel = |channel: f32| (channel + (luma - channel) * desaturate) * brightness;
+
+        // The setters clamp, so filters that were never validated can't
+        // produce an invalid colour.
+        let mut color = Color::TRANSPARENT;
+        color.set_red(channel(red).min(1.0));
+        color.set_green(channel(green).min(1.0));
+        color.set_blue(channel(blue).min(1.0));
+        color.set_alpha(straight.alpha() as f32 / 255.0 * opacity);
+
+        *pixel = color.premultiply().to_color_u8();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::PremultipliedColorU8;
+
+    #[test]
+    fn filters_pixels() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        pixmap.pixels_mut()[0] = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+
+        filter(
+            &mut pixmap,
+            &StateFilter {
+                desaturate: 1.0,
+                opacity: 0.5,
+                ..Default::default()
+            },
+        );
+
+        // Fully desaturated red is a dark grey, at half opacity.
+        assert_eq!(
+            pixmap.pixels(),
+            [
+                PremultipliedColorU8::from_rgba(27, 27, 27, 128).unwrap(),
+                PremultipliedColorU8::TRANSPARENT,
+            ]
+        );
+    }
+
+    #[test]
+    fn never_panics_on_unchecked_filters() {
+        let mut pixmap = Pixmap::new(1, 1).unwrap();
+        pixmap.pixels_mut()[0] = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+
+        filter(
+            &mut pixmap,
+            &StateFilter {
+                desaturate: f32::NAN,
+                brightness: f32::INFINITY,
+                opacity: f32::NAN,
+            },
+        );
+
+        assert_eq!(pixmap.pixels(), [PremultipliedColorU8::TRANSPARENT]);
+    }
+
+    #[test]
+    fn compares_like_it_hashes() {
+        use std::hash::{BuildHasher, RandomState};
+
+        let hasher = RandomState::new();
+        let zero = StateFilter {
+            desaturate: 0.0,
+            ..Default::default()
+        };
+        let negative_zero = StateFilter {
+            desaturate: -0.0,
+            ..Default::default()
+        };
+        assert_eq!(zero, negative_zero);
+        assert_eq!(hasher.hash_one(zero), hasher.hash_one(negative_zero));
+
+        let nan = StateFilter {
+            opacity: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(nan, nan);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rejects_filters_out_of_range() {
+        let parse = |toml: &str| toml::from_str::<StateFilter>(toml);
+
+        assert_eq!(parse("opacity = 0.5").unwrap().opacity, 0.5);
+        assert_eq!(parse("desaturate = 1").unwrap().desaturate, 1.0);
+        assert!(parse("opacity = 1.5").is_err());
+        assert!(parse("opacity = nan").is_err());
+        assert!(parse("desaturate = -1.0").is_err());
+        assert!(parse("brightness = inf").is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod alpha_bleed;
+pub mod extrude;
+pub mod filter;
+pub mod placeholder;
+pub mod recolor;
+pub mod rotate;
+pub mod trim;
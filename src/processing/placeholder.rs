@@ -0,0 +1,64 @@
+use tiny_skia::{Color, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+/// Draws an obviously broken `width` by `height` image, a magenta and black
+/// checkerboard crossed out with a magenta X, to stand in for a sprite that
+/// couldn't be fetched.
+pub fn placeholder(width: u32, height: u32) -> Option<Pixmap> {
+    let magenta = Color::from_rgba8(255, 0, 255, 255);
+
+    let mut pixmap = Pixmap::new(width, height)?;
+    pixmap.fill(Color::BLACK);
+
+    // Four checks across the shorter side.
+    let check = (width.min(height) / 4).max(1);
+    let magenta_pixel = magenta.premultiply().to_color_u8();
+    let stride = width as usize;
+    for (index, pixel) in pixmap.pixels_mut().iter_mut().enumerate() {
+        let (x, y) = ((index % stride) as u32, (index / stride) as u32);
+        if (x / check + y / check).is_multiple_of(2) {
+            *pixel = magenta_pixel;
+        }
+    }
+
+    let mut cross = PathBuilder::new();
+    cross.move_to(0.0, 0.0);
+    cross.line_to(width as f32, height as f32);
+    cross.move_to(width as f32, 0.0);
+    cross.line_to(0.0, height as f32);
+
+    let mut paint = Paint::default();
+    paint.set_color(magenta);
+
+    pixmap.stroke_path(
+        &cross.finish()?,
+        &paint,
+        &Stroke {
+            width: (width.min(height) as f32 / 8.0).max(1.0),
+            ..Default::default()
+        },
+        Transform::identity(),
+        None,
+    );
+
+    Some(pixmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::PremultipliedColorU8;
+
+    #[test]
+    fn draws_a_crossed_out_checkerboard() {
+        let pixmap = placeholder(16, 16).unwrap();
+        let magenta = PremultipliedColorU8::from_rgba(255, 0, 255, 255).unwrap();
+        let black = PremultipliedColorU8::from_rgba(0, 0, 0, 255).unwrap();
+
+        assert_eq!(pixmap.pixel(0, 0), Some(magenta));
+        assert_eq!(pixmap.pixel(6, 0), Some(black));
+        assert_eq!(pixmap.pixel(8, 0), Some(magenta));
+        // The X is drawn over the black checks too.
+        assert_eq!(pixmap.pixel(4, 11), Some(magenta));
+        assert!(pixmap.pixels().iter().all(|pixel| pixel.alpha() == 255));
+    }
+}
@@ -0,0 +1,185 @@
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use tiny_skia::{Color, Pixmap};
+
+/// An opaque colour, written as `#rgb` or `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct HexColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl HexColor {
+    pub const WHITE: Self = Self {
+        red: 255,
+        green: 255,
+        blue: 255,
+    };
+}
+
+impl FromStr for HexColor {
+    type Err = anyhow::Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let hex = str
+            .strip_prefix('#')
+            .with_context(|| format!("colour {str} should start with #"))?;
+
+        if !hex.is_ascii() {
+            bail!("{str} is not a hex colour");
+        }
+
+        let channel = |digits: &str| {
+            u8::from_str_radix(digits, 16).with_context(|| format!("{str} is not a hex colour"))
+        };
+
+        match hex.len() {
+            3 => Ok(Self {
+                red: channel(&hex[0..1].repeat(2))?,
+                green: channel(&hex[1..2].repeat(2))?,
+                blue: channel(&hex[2..3].repeat(2))?,
+            }),
+            6 => Ok(Self {
+                red: channel(&hex[0..2])?,
+                green: channel(&hex[2..4])?,
+                blue: channel(&hex[4..6])?,
+            }),
+            _ => bail!("colour {str} should be written as #rgb or #rrggbb"),
+        }
+    }
+}
+
+impl TryFrom<String> for HexColor {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Replaces a colour with `color` if there is one, then multiplies it by
+/// `tint` if there is one.
+fn recolor_rgb(
+    (red, green, blue): (u8, u8, u8),
+    color: Option<HexColor>,
+    tint: Option<HexColor>,
+) -> (u8, u8, u8) {
+    let (red, green, blue) = match color {
+        Some(color) => (color.red, color.green, color.blue),
+        None => (red, green, blue),
+    };
+
+    match tint {
+        Some(tint) => {
+            let multiply = |channel: u8, by: u8| ((channel as u16 * by as u16 + 127) / 255) as u8;
+            (
+                multiply(red, tint.red),
+                multiply(green, tint.green),
+                multiply(blue, tint.blue),
+            )
+        }
+        None => (red, green, blue),
+    }
+}
+
+/// Recolours every pixel of a pixmap, keeping its alpha.
+pub fn recolor_pixmap(pixmap: &mut Pixmap, color: Option<HexColor>, tint: Option<HexColor>) {
+    for pixel in pixmap.pixels_mut() {
+        if pixel.alpha() == 0 {
+            continue;
+        }
+
+        let straight = pixel.demultiply();
+        let (red, green, blue) = recolor_rgb(
+            (straight.red(), straight.green(), straight.blue()),
+            color,
+            tint,
+        );
+
+        *pixel = Color::from_rgba8(red, green, blue, straight.alpha())
+            .premultiply()
+            .to_color_u8();
+    }
+}
+
+lazy_static! {
+    static ref SVG_COLOR_REGEX: Regex =
+        Regex::new("(fill|stroke|stop-color|flood-color|lighting-color)=\"#([0-9a-fA-F]{6})\"")
+            .unwrap();
+}
+
+/// Recolours every fill, stroke and gradient stop of an SVG written by usvg,
+/// which always writes colours as `#rrggbb` attributes and has already
+/// resolved `currentColor`.
+pub fn recolor_svg(svg: &str, color: Option<HexColor>, tint: Option<HexColor>) -> String {
+    SVG_COLOR_REGEX
+        .replace_all(svg, |captures: &Captures| {
+            let original: HexColor = format!("#{}", &captures[2])
+                .parse()
+                .expect("regex should only match hex colours");
+            let (red, green, blue) =
+                recolor_rgb((original.red, original.green, original.blue), color, tint);
+
+            format!("{}=\"#{red:02x}{green:02x}{blue:02x}\"", &captures[1])
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::PremultipliedColorU8;
+
+    #[test]
+    fn parses_hex_colours() {
+        let orange = HexColor {
+            red: 255,
+            green: 136,
+            blue: 0,
+        };
+
+        assert_eq!("#ff8800".parse::<HexColor>().unwrap(), orange);
+        assert_eq!("#F80".parse::<HexColor>().unwrap(), orange);
+        assert!("ff8800".parse::<HexColor>().is_err());
+        assert!("#ff88".parse::<HexColor>().is_err());
+    }
+
+    #[test]
+    fn recolors_svg_fills_and_strokes() {
+        let svg =
+            r##"<path fill="#000000" stroke="#ff0000" fill-opacity="0.5"/><path fill="none"/>"##;
+        let white = Some(HexColor::WHITE);
+
+        assert_eq!(
+            recolor_svg(svg, white, None),
+            r##"<path fill="#ffffff" stroke="#ffffff" fill-opacity="0.5"/><path fill="none"/>"##
+        );
+        assert_eq!(
+            recolor_svg(svg, white, "#808080".parse().ok()),
+            r##"<path fill="#808080" stroke="#808080" fill-opacity="0.5"/><path fill="none"/>"##
+        );
+    }
+
+    #[test]
+    fn recolors_pixmaps_keeping_alpha() {
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        pixmap.pixels_mut()[0] = PremultipliedColorU8::from_rgba(128, 128, 128, 128).unwrap();
+
+        recolor_pixmap(&mut pixmap, "#ff0000".parse().ok(), None);
+
+        assert_eq!(
+            pixmap.pixels(),
+            [
+                PremultipliedColorU8::from_rgba(128, 0, 0, 128).unwrap(),
+                PremultipliedColorU8::TRANSPARENT,
+            ]
+        );
+    }
+}
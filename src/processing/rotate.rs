@@ -0,0 +1,64 @@
+use tiny_skia::{Pixmap, PixmapPaint, Transform};
+
+/// Turns a pixmap clockwise by `quarter_turns` multiples of 90 degrees,
+/// swapping its width and height for odd turns.
+pub fn rotate(pixmap: &Pixmap, quarter_turns: u32) -> Option<Pixmap> {
+    let (width, height) = (pixmap.width(), pixmap.height());
+
+    let (rotated_width, rotated_height, transform) = match quarter_turns % 4 {
+        0 => return Some(pixmap.clone()),
+        1 => (
+            height,
+            width,
+            Transform::from_rotate(90.0).post_translate(height as f32, 0.0),
+        ),
+        2 => (
+            width,
+            height,
+            Transform::from_rotate(180.0).post_translate(width as f32, height as f32),
+        ),
+        _ => (
+            height,
+            width,
+            Transform::from_rotate(270.0).post_translate(0.0, width as f32),
+        ),
+    };
+
+    let mut rotated = Pixmap::new(rotated_width, rotated_height)?;
+    rotated.draw_pixmap(
+        0,
+        0,
+        pixmap.as_ref(),
+        &PixmapPaint::default(),
+        transform,
+        None,
+    );
+
+    Some(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::{Color, PremultipliedColorU8};
+
+    #[test]
+    fn rotates_clockwise() {
+        // A 2x1 pixmap with a red pixel on the left.
+        let mut pixmap = Pixmap::new(2, 1).unwrap();
+        pixmap.pixels_mut()[0] = Color::from_rgba8(255, 0, 0, 255)
+            .premultiply()
+            .to_color_u8();
+        let red = PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap();
+
+        let quarter = rotate(&pixmap, 1).unwrap();
+        assert_eq!((quarter.width(), quarter.height()), (1, 2));
+        assert_eq!(quarter.pixel(0, 0), Some(red));
+
+        let half = rotate(&pixmap, 2).unwrap();
+        assert_eq!(half.pixel(1, 0), Some(red));
+
+        let three_quarters = rotate(&pixmap, 3).unwrap();
+        assert_eq!(three_quarters.pixel(0, 1), Some(red));
+    }
+}
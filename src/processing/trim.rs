@@ -0,0 +1,66 @@
+use tiny_skia::{IntRect, Pixmap};
+
+/// The smallest rect holding every visible pixel of a pixmap, or `None` when
+/// it's fully transparent.
+pub fn visible_bounds(pixmap: &Pixmap) -> Option<IntRect> {
+    let width = pixmap.width();
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+
+    for (index, pixel) in pixmap.pixels().iter().enumerate() {
+        if pixel.alpha() == 0 {
+            continue;
+        }
+
+        let (x, y) = (index as u32 % width, index as u32 / width);
+        bounds = Some(match bounds {
+            None => (x, y, x, y),
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+        });
+    }
+
+    let (left, top, right, bottom) = bounds?;
+    IntRect::from_ltrb(left as i32, top as i32, right as i32 + 1, bottom as i32 + 1)
+}
+
+/// Crops a pixmap down to the smallest rect holding every visible pixel,
+/// returning the cropped pixmap along with where it sat in the original. Fully
+/// transparent pixmaps are cropped to a single pixel.
+pub fn trim(pixmap: &Pixmap) -> (Pixmap, u32, u32) {
+    let rect = visible_bounds(pixmap).unwrap_or_else(|| IntRect::from_xywh(0, 0, 1, 1).unwrap());
+
+    let trimmed = pixmap
+        .clone_rect(rect)
+        .expect("trimmed rect should be inside the pixmap");
+
+    (trimmed, rect.x() as u32, rect.y() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::{Color, Paint, Rect, Transform};
+
+    #[test]
+    fn crops_to_visible_pixels() {
+        let mut pixmap = Pixmap::new(10, 8).unwrap();
+        let mut paint = Paint::default();
+        paint.set_color(Color::WHITE);
+        pixmap.fill_rect(
+            Rect::from_xywh(2.0, 3.0, 5.0, 4.0).unwrap(),
+            &paint,
+            Transform::identity(),
+            None,
+        );
+
+        let (trimmed, x, y) = trim(&pixmap);
+        assert_eq!((trimmed.width(), trimmed.height(), x, y), (5, 4, 2, 3));
+    }
+
+    #[test]
+    fn crops_transparent_pixmaps_to_a_pixel() {
+        let (trimmed, x, y) = trim(&Pixmap::new(10, 8).unwrap());
+        assert_eq!((trimmed.width(), trimmed.height(), x, y), (1, 1, 0, 0));
+    }
+}
@@ -0,0 +1,369 @@
+use std::{convert::Infallible, fs, path::PathBuf, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+use reqwest::{StatusCode, Url};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use serde::Deserialize;
+
+use crate::lockfile::{LockMode, Pins};
+
+/// Where fetched icons are cached, relative to the working directory.
+const DEFAULT_CACHE_DIR: &str = ".springroll/cache";
+
+/// Where a source reads its icons from, written as an `http(s)://` URL, a
+/// `file://` URL or a plain path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "String"))]
+pub enum SourceRoot {
+    Url(String),
+    Path(PathBuf),
+}
+
+impl FromStr for SourceRoot {
+    type Err = Infallible;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        if str.starts_with("http://") || str.starts_with("https://") {
+            return Ok(Self::Url(str.trim_end_matches('/').to_string()));
+        }
+
+        // Anything that isn't a valid file URL is kept as a path, so it fails
+        // with the path in the error when it's read.
+        let path = match str.starts_with("file://") {
+            true => Url::parse(str)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .unwrap_or_else(|| PathBuf::from(str)),
+            false => PathBuf::from(str),
+        };
+
+        Ok(Self::Path(path))
+    }
+}
+
+impl From<String> for SourceRoot {
+    fn from(value: String) -> Self {
+        let Ok(root) = value.parse();
+        root
+    }
+}
+
+/// Appends each segment of `path` to `base_url`, escaping them as needed.
+fn join_url(base_url: &str, path: &str) -> Result<Url> {
+    let mut url = Url::parse(base_url).with_context(|| format!("{base_url} is not a valid URL"))?;
+    url.path_segments_mut()
+        .ok()
+        .with_context(|| format!("{base_url} can't have a path"))?
+        .pop_if_empty()
+        .extend(path.split('/'));
+
+    Ok(url)
+}
+
+impl SourceRoot {
+    /// Reads the file at `path` under this root, such as `house.svg`.
+    pub async fn fetch_text(&self, context: &FetchContext, path: &str) -> Result<String> {
+        match self {
+            Self::Url(base_url) => {
+                let url = join_url(base_url, path)?;
+
+                let response = context
+                    .client
+                    .get(url.clone())
+                    .header("cache-control", "public, max-age=3600")
+                    .send()
+                    .await
+                    .with_context(|| format!("failed to fetch {url}"))?;
+
+                // The cache answers misses with a gateway timeout when it's
+                // not allowed to go to the network.
+                if context.offline && response.status() == StatusCode::GATEWAY_TIMEOUT {
+                    bail!("{url} isn't cached yet, run once without --offline to fetch it");
+                }
+
+                let bytes = response
+                    .error_for_status()
+                    .with_context(|| format!("failed to fetch {url}"))?
+                    .bytes()
+                    .await
+                    .with_context(|| format!("failed to fetch {url}"))?;
+
+                context.pins.check(url.as_str(), &bytes)?;
+
+                String::from_utf8(bytes.to_vec()).context("failed to parse fetched as text")
+            }
+            Self::Path(root) => {
+                let path = root.join(path);
+                fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Settings for a source shared by every sprite using it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SourceConfig {
+    /// Used by sprites of this source that don't set their own `base_url`.
+    pub base_url: Option<SourceRoot>,
+}
+
+/// The `[sources]` table, configuring each remote source.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SourcesConfig {
+    pub fluent: SourceConfig,
+    /// The Font Awesome package used by sprites that don't set their own
+    /// `root`. Font Awesome is always read from disk, so this is a path.
+    pub font_awesome: Option<SourceRoot>,
+    pub luicide: SourceConfig,
+    pub material_symbols: SourceConfig,
+}
+
+/// The `[cache]` table, configuring where fetched icons are kept between runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: PathBuf::from(DEFAULT_CACHE_DIR),
+        }
+    }
+}
+
+/// Everything sources need to fetch icons.
+#[derive(Debug, Clone)]
+pub struct FetchContext {
+    pub client: ClientWithMiddleware,
+    pub sources: SourcesConfig,
+    /// Whether responses only come from the cache, without going to the
+    /// network.
+    pub offline: bool,
+    /// Checks everything fetched over HTTP against `Springroll.lock`.
+    pub pins: Pins,
+}
+
+impl FetchContext {
+    /// Creates a context whose HTTP responses are cached on disk according to
+    /// `cache`. When `offline`, icons are only ever read from the cache.
+    pub fn new(
+        sources: SourcesConfig,
+        cache: &CacheConfig,
+        offline: bool,
+        pins: Pins,
+    ) -> Result<Self> {
+        let reqwest = reqwest::Client::builder()
+            .build()
+            .context("failed to create reqwest client")?;
+
+        let client = match cache.enabled {
+            true => ClientBuilder::new(reqwest)
+                .with(Cache(HttpCache {
+                    // Updating pins checks every cached icon is still current.
+                    mode: match (offline, pins.mode()) {
+                        (true, _) => CacheMode::OnlyIfCached,
+                        (false, LockMode::Update) => CacheMode::NoCache,
+                        (false, _) => CacheMode::Default,
+                    },
+                    manager: CACacheManager::new(cache.dir.clone(), true),
+                    options: HttpCacheOptions::default(),
+                }))
+                .build(),
+            false if offline => bail!("--offline needs the cache to be enabled"),
+            false => ClientWithMiddleware::from(reqwest),
+        };
+
+        Ok(Self {
+            client,
+            sources,
+            offline,
+            pins,
+        })
+    }
+
+    /// The root a sprite reads from, picking its own `base_url` first, then
+    /// the one in `[sources]`, then the source's default.
+    pub fn root(
+        &self,
+        base_url: &Option<SourceRoot>,
+        config: impl Fn(&SourcesConfig) -> &SourceConfig,
+        default: &str,
+    ) -> SourceRoot {
+        base_url
+            .clone()
+            .or_else(|| config(&self.sources).base_url.clone())
+            .unwrap_or_else(|| SourceRoot::from(default.to_string()))
+    }
+}
+
+/// A local HTTP server for tests, serving each file after its delay and
+/// answering anything else with a 404.
+#[cfg(test)]
+pub(crate) struct TestServer {
+    pub url: String,
+    /// How many requests were made, served or not.
+    pub requests: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    /// The most requests that were being served at once.
+    pub max_in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl TestServer {
+    pub fn new(files: &[(&str, &str, std::time::Duration)]) -> Self {
+        use std::{
+            collections::HashMap,
+            io::{Read, Write},
+            net::TcpListener,
+            sync::{Arc, atomic::Ordering},
+            thread,
+        };
+
+        let files: Arc<HashMap<String, (String, std::time::Duration)>> = Arc::new(
+            files
+                .iter()
+                .map(|(path, body, delay)| (format!("/{path}"), (body.to_string(), *delay)))
+                .collect(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            requests: Default::default(),
+            max_in_flight: Default::default(),
+        };
+
+        let requests = server.requests.clone();
+        let max_in_flight = server.max_in_flight.clone();
+        let in_flight = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (files, requests, max_in_flight, in_flight) = (
+                    files.clone(),
+                    requests.clone(),
+                    max_in_flight.clone(),
+                    in_flight.clone(),
+                );
+
+                thread::spawn(move || {
+                    let mut request = [0; 4096];
+                    let read = stream.read(&mut request).unwrap_or(0);
+                    let request = String::from_utf8_lossy(&request[..read]);
+                    let path = request.split(' ').nth(1).unwrap_or_default();
+
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+
+                    let response = match files.get(path) {
+                        Some((body, delay)) => {
+                            thread::sleep(*delay);
+                            format!(
+                                "HTTP/1.1 200 OK\r\ncache-control: public, max-age=3600\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                                body.len()
+                            )
+                        }
+                        None => {
+                            "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                                .to_string()
+                        }
+                    };
+
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let _ = stream.write_all(response.as_bytes());
+                });
+            }
+        });
+
+        server
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn parses_source_roots() {
+        assert_eq!(
+            "https://example.com/icons/".parse::<SourceRoot>().unwrap(),
+            SourceRoot::Url("https://example.com/icons".to_string())
+        );
+        assert_eq!(
+            "file:///srv/icons".parse::<SourceRoot>().unwrap(),
+            SourceRoot::Path(PathBuf::from("/srv/icons"))
+        );
+        assert_eq!(
+            "vendor/icons".parse::<SourceRoot>().unwrap(),
+            SourceRoot::Path(PathBuf::from("vendor/icons"))
+        );
+    }
+
+    #[test]
+    fn escapes_url_paths() {
+        assert_eq!(
+            join_url("https://example.com/icons", "arrow left/24 regular.svg")
+                .unwrap()
+                .as_str(),
+            "https://example.com/icons/arrow%20left/24%20regular.svg"
+        );
+        assert_eq!(
+            join_url("https://example.com", "#1?.svg").unwrap().as_str(),
+            "https://example.com/%231%3F.svg"
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_cached_icons_offline() {
+        let server = TestServer::new(&[("a.svg", "<svg/>", Duration::ZERO)]);
+        let root = SourceRoot::Url(server.url.clone());
+
+        let dir = std::env::temp_dir().join(format!("springroll-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = CacheConfig {
+            enabled: true,
+            dir: dir.clone(),
+        };
+
+        let online =
+            FetchContext::new(SourcesConfig::default(), &cache, false, Pins::default()).unwrap();
+        assert_eq!(root.fetch_text(&online, "a.svg").await.unwrap(), "<svg/>");
+
+        let offline =
+            FetchContext::new(SourcesConfig::default(), &cache, true, Pins::default()).unwrap();
+        assert_eq!(root.fetch_text(&offline, "a.svg").await.unwrap(), "<svg/>");
+
+        let error = root.fetch_text(&offline, "b.svg").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}/b.svg isn't cached yet, run once without --offline to fetch it",
+                server.url
+            )
+        );
+
+        // Only the online fetch went to the network.
+        assert_eq!(server.requests.load(Ordering::SeqCst), 1);
+
+        let uncached = CacheConfig {
+            enabled: false,
+            ..cache
+        };
+        assert!(
+            FetchContext::new(SourcesConfig::default(), &uncached, true, Pins::default()).is_err()
+        );
+    }
+}
@@ -1,4 +1,15 @@
+use anyhow::{Context, Result};
 use serde::Deserialize;
+use usvg::{Options, Tree};
+
+use crate::sources::{
+    SpriteSource,
+    fetch::{FetchContext, SourceRoot},
+};
+
+/// Where `fluentui-system-icons` is fetched from, unless `base_url` is set.
+const DEFAULT_BASE_URL: &str =
+    "https://raw.githubusercontent.com/microsoft/fluentui-system-icons/main";
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -9,16 +20,94 @@ pub enum FluentStyle {
     Outlined,
 }
 
+impl FluentStyle {
+    /// The style as it's written in icon file names.
+    pub fn file_style(&self) -> &'static str {
+        match self {
+            Self::Filled => "filled",
+            Self::Outlined => "regular",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub struct FluentSource {
+    /// The icon's name in snake case, such as `add_circle`.
     #[cfg_attr(feature = "serde", serde(rename = "fluent"))]
     icon: String,
     #[serde(default)]
     style: FluentStyle,
+    /// Which of the icon's sizes to use, such as `16`, `24` or `48`.
+    #[cfg_attr(feature = "serde", serde(default = "default_size"))]
+    size: u32,
+    /// Reads icons from a mirror or local checkout of the repository instead
+    /// of GitHub.
+    #[cfg_attr(feature = "serde", serde(default))]
+    base_url: Option<SourceRoot>,
+}
+
+#[cfg(feature = "serde")]
+fn default_size() -> u32 {
+    24
 }
 
 impl FluentSource {
-    pub async fn fetch(&self) {}
+    /// Where the icon is in the repository, such as
+    /// `assets/Add Circle/SVG/ic_fluent_add_circle_24_filled.svg`.
+    pub fn asset_path(&self) -> String {
+        // Folders are named after the icon in title case.
+        let folder = self
+            .icon
+            .split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        format!(
+            "assets/{folder}/SVG/ic_fluent_{}_{}_{}.svg",
+            self.icon,
+            self.size,
+            self.style.file_style()
+        )
+    }
+
+    pub async fn fetch(&self, context: &FetchContext) -> Result<SpriteSource> {
+        let raw_icon = context
+            .root(&self.base_url, |sources| &sources.fluent, DEFAULT_BASE_URL)
+            .fetch_text(context, &self.asset_path())
+            .await
+            .context("failed to fetch icon")?;
+
+        let svg = Tree::from_str(&raw_icon, &Options::default()).context("failed to parse svg")?;
+
+        Ok(SpriteSource::Tree(svg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_asset_paths() {
+        let source = FluentSource {
+            icon: "add_circle".to_string(),
+            style: FluentStyle::Outlined,
+            size: 20,
+            base_url: None,
+        };
+
+        assert_eq!(
+            source.asset_path(),
+            "assets/Add Circle/SVG/ic_fluent_add_circle_20_regular.svg"
+        );
+    }
 }
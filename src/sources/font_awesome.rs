@@ -1,4 +1,17 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
 use serde::Deserialize;
+use usvg::{Options, Tree};
+
+use crate::sources::{
+    SpriteSource,
+    fetch::{FetchContext, SourceRoot},
+};
+
+/// Where `@fortawesome/fontawesome-free` is installed by npm, relative to the
+/// working directory.
+const DEFAULT_ROOT: &str = "node_modules/@fortawesome/fontawesome-free";
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -11,6 +24,17 @@ pub enum FontAwesomeStyle {
     Thin,
 }
 
+impl FontAwesomeStyle {
+    pub fn folder(&self) -> &'static str {
+        match self {
+            Self::Solid => "solid",
+            Self::Regular => "regular",
+            Self::Light => "light",
+            Self::Thin => "thin",
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
@@ -23,6 +47,21 @@ pub enum FontAwesomePack {
     SharpDuotone,
 }
 
+impl FontAwesomePack {
+    /// The folder under `svgs/` holding icons of this pack in `style`.
+    /// Brands only come in one style.
+    pub fn folder(&self, style: &FontAwesomeStyle) -> String {
+        match (self, style) {
+            (Self::Brand, _) => "brands".to_string(),
+            (Self::Classic, style) => style.folder().to_string(),
+            (Self::Duotone, FontAwesomeStyle::Solid) => "duotone".to_string(),
+            (Self::Duotone, style) => format!("duotone-{}", style.folder()),
+            (Self::Sharp, style) => format!("sharp-{}", style.folder()),
+            (Self::SharpDuotone, style) => format!("sharp-duotone-{}", style.folder()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
@@ -33,8 +72,117 @@ pub struct FontAwesomeSource {
     style: FontAwesomeStyle,
     #[serde(default)]
     pack: FontAwesomePack,
+    /// The Font Awesome package to read icons from, or its `svgs` folder.
+    /// Defaults to `node_modules/@fortawesome/fontawesome-free`, and can point
+    /// at a licensed copy of Font Awesome Pro instead. Overrides `font_awesome`
+    /// in `[sources]`.
+    root: Option<PathBuf>,
 }
 
 impl FontAwesomeSource {
-    pub async fn fetch(&self) {}
+    pub async fn fetch(&self, context: &FetchContext) -> Result<SpriteSource> {
+        let root = match (&self.root, &context.sources.font_awesome) {
+            (Some(root), _) | (None, Some(SourceRoot::Path(root))) => root.clone(),
+            (None, Some(SourceRoot::Url(url))) => {
+                bail!("Font Awesome is read from disk, so its root can't be the URL {url}")
+            }
+            (None, None) => PathBuf::from(DEFAULT_ROOT),
+        };
+        let svgs = match root.join("svgs").is_dir() {
+            true => root.join("svgs"),
+            false => root,
+        };
+
+        let path = svgs
+            .join(self.pack.folder(&self.style))
+            .join(format!("{}.svg", self.icon));
+
+        let svg = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read icon from {}", path.display()))?;
+        let tree = Tree::from_str(&svg, &Options::default()).context("failed to parse svg")?;
+
+        Ok(SpriteSource::Tree(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_packs_and_styles_to_folders() {
+        let folder = |pack: FontAwesomePack, style| pack.folder(&style);
+
+        assert_eq!(
+            folder(FontAwesomePack::Classic, FontAwesomeStyle::Regular),
+            "regular"
+        );
+        assert_eq!(
+            folder(FontAwesomePack::Brand, FontAwesomeStyle::Solid),
+            "brands"
+        );
+        assert_eq!(
+            folder(FontAwesomePack::Duotone, FontAwesomeStyle::Solid),
+            "duotone"
+        );
+        assert_eq!(
+            folder(FontAwesomePack::Sharp, FontAwesomeStyle::Light),
+            "sharp-light"
+        );
+        assert_eq!(
+            folder(FontAwesomePack::SharpDuotone, FontAwesomeStyle::Thin),
+            "sharp-duotone-thin"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_sources_root() {
+        use crate::{
+            lockfile::Pins,
+            sources::fetch::{CacheConfig, SourcesConfig},
+        };
+        use futures::executor::block_on;
+
+        let dir =
+            std::env::temp_dir().join(format!("springroll-font-awesome-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (package, size) in [("shared", 4), ("own", 8)] {
+            let solid = dir.join(package).join("svgs").join("solid");
+            fs::create_dir_all(&solid).unwrap();
+            fs::write(
+                solid.join("house.svg"),
+                format!(
+                    r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}"/>"#
+                ),
+            )
+            .unwrap();
+        }
+
+        let context = FetchContext::new(
+            SourcesConfig {
+                font_awesome: Some(SourceRoot::Path(dir.join("shared"))),
+                ..Default::default()
+            },
+            &CacheConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            false,
+            Pins::default(),
+        )
+        .unwrap();
+
+        let size = |root: Option<PathBuf>| {
+            let source = FontAwesomeSource {
+                icon: "house".to_string(),
+                style: FontAwesomeStyle::Solid,
+                pack: FontAwesomePack::Classic,
+                root,
+            };
+            block_on(source.fetch(&context)).unwrap().size()
+        };
+
+        assert_eq!(size(None), (4.0, 4.0));
+        assert_eq!(size(Some(dir.join("own"))), (8.0, 8.0));
+    }
 }
@@ -0,0 +1,132 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::{Context, Result, bail};
+use futures::future::try_join_all;
+use serde::Deserialize;
+use tiny_skia::Transform;
+
+use crate::sources::{SourceLayer, SpriteSource, SpriteSpecifier, fetch::FetchContext};
+
+/// Other sources drawn on top of each other into one sprite, such as an icon
+/// with a badge.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct LayersSource {
+    /// From the bottom up.
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Layer {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    source: SpriteSpecifier,
+    /// How far right the layer is drawn, in the intrinsic pixels of the
+    /// layers.
+    #[cfg_attr(feature = "serde", serde(default))]
+    offset_x: f32,
+    /// How far down the layer is drawn, in the intrinsic pixels of the
+    /// layers.
+    #[cfg_attr(feature = "serde", serde(default))]
+    offset_y: f32,
+    /// Multiplies the intrinsic size of the layer's source.
+    #[cfg_attr(feature = "serde", serde(default = "default_one"))]
+    scale: f32,
+    #[cfg_attr(feature = "serde", serde(default = "default_one"))]
+    opacity: f32,
+}
+
+#[cfg(feature = "serde")]
+fn default_one() -> f32 {
+    1.0
+}
+
+impl Layer {
+    /// The floats compared and hashed, with `-0.0` counted as `0.0`.
+    fn normalized_bits(&self) -> [u32; 4] {
+        [self.offset_x, self.offset_y, self.scale, self.opacity]
+            .map(|value| (value + 0.0).to_bits())
+    }
+}
+
+// Sprite specifiers are hashable, so layers compare and hash their floats by
+// the same bits, which keeps `Eq` reflexive even for NaN.
+impl PartialEq for Layer {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source && self.normalized_bits() == other.normalized_bits()
+    }
+}
+
+impl Eq for Layer {}
+
+impl Hash for Layer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+        self.normalized_bits().hash(state);
+    }
+}
+
+impl LayersSource {
+    pub async fn fetch(&self, context: &FetchContext) -> Result<SpriteSource> {
+        if self.layers.is_empty() {
+            bail!("layers source has no layers");
+        }
+
+        let sources = try_join_all(self.layers.iter().enumerate().map(|(index, layer)| {
+            async move {
+                // Layers can hold more layers, so the fetch is boxed to give
+                // the recursive future a size.
+                Box::pin(layer.source.fetch(context))
+                    .await
+                    .with_context(|| format!("failed to fetch layer {index}"))
+            }
+        }))
+        .await?;
+
+        // Layers offset up or left of the others move everything else down
+        // and right, so the layers start at 0, 0.
+        let (min_x, min_y) = self.layers.iter().fold((0.0f32, 0.0f32), |(x, y), layer| {
+            (x.min(layer.offset_x), y.min(layer.offset_y))
+        });
+
+        Ok(SpriteSource::Layers(
+            self.layers
+                .iter()
+                .zip(sources)
+                .map(|(layer, source)| SourceLayer {
+                    source,
+                    transform: Transform::from_scale(layer.scale, layer.scale)
+                        .post_translate(layer.offset_x - min_x, layer.offset_y - min_y),
+                    opacity: layer.opacity,
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, RandomState};
+
+    use super::*;
+
+    #[test]
+    fn compares_like_it_hashes() {
+        let zero: Layer = toml::from_str("path = \"badge.png\"").unwrap();
+        let negative_zero = Layer {
+            offset_x: -0.0,
+            offset_y: -0.0,
+            ..zero.clone()
+        };
+
+        let hasher = RandomState::new();
+        assert_eq!(zero, negative_zero);
+        assert_eq!(hasher.hash_one(&zero), hasher.hash_one(&negative_zero));
+
+        let nan = Layer {
+            offset_x: f32::NAN,
+            ..zero
+        };
+        assert_eq!(nan, nan.clone());
+    }
+}
@@ -2,13 +2,22 @@ use anyhow::{Context, Ok, Result};
 use serde::Deserialize;
 use usvg::{Options, Tree};
 
-use crate::sources::SpriteSource;
+use crate::sources::{
+    SpriteSource,
+    fetch::{FetchContext, SourceRoot},
+};
+
+const LUICIDE_URL: &str =
+    "https://raw.githubusercontent.com/lucide-icons/lucide/refs/heads/master/icons";
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 pub struct LuicideSource {
     #[cfg_attr(feature = "serde", serde(rename = "luicide"))]
     icon: String,
+    /// Reads icons from a mirror of Lucide's `icons` folder instead of GitHub.
+    #[cfg_attr(feature = "serde", serde(default))]
+    base_url: Option<SourceRoot>,
     // #[serde(default = "default_luicide_stroke_width")]
     // stroke_width: u16,
 }
@@ -18,21 +27,12 @@ pub struct LuicideSource {
 // }
 
 impl LuicideSource {
-    pub async fn fetch(&self, reqwest: reqwest::Client) -> Result<SpriteSource> {
-        let url = format!(
-            "https://raw.githubusercontent.com/lucide-icons/lucide/refs/heads/master/icons/{}.svg",
-            self.icon
-        );
-
-        let raw_icon = reqwest
-            .get(url)
-            .header("cache-control", "public, max-age=3600")
-            .send()
-            .await
-            .context("failed to fetch icon")?
-            .text()
+    pub async fn fetch(&self, context: &FetchContext) -> Result<SpriteSource> {
+        let raw_icon = context
+            .root(&self.base_url, |sources| &sources.luicide, LUICIDE_URL)
+            .fetch_text(context, &format!("{}.svg", self.icon))
             .await
-            .context("failed to parse fetched as text")?;
+            .context("failed to fetch icon")?;
 
         let svg = Tree::from_str(&raw_icon, &Options::default()).context("failed to parse svg")?;
         // for node in svg.clip_paths() {}
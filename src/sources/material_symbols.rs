@@ -1,15 +1,17 @@
 use anyhow::Context;
-use reqwest::Url;
 use serde::Deserialize;
 
-use crate::sources::SpriteSource;
+use crate::{
+    processing::recolor::HexColor,
+    sources::{
+        SpriteSource,
+        fetch::{FetchContext, SourceRoot},
+    },
+};
 
 const MATERIAL_SYMBOLS_URL: &str =
     "https://raw.githubusercontent.com/google/material-design-icons/refs/heads/master/symbols/web";
 
-const PATH: &str = "<path ";
-const PATH_REPLACE_WITH_WHITE_FILL: &str = "<path fill=\"#fff\" ";
-
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Deserialize))]
 #[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
@@ -146,6 +148,10 @@ pub struct MaterialSymbolsSource {
     grade: MaterialSymbolsGrade,
     #[cfg_attr(feature = "serde", serde(default))]
     size: MaterialSymbolsSize,
+    /// Reads symbols from a mirror of the `symbols/web` folder instead of
+    /// GitHub.
+    #[cfg_attr(feature = "serde", serde(default))]
+    base_url: Option<SourceRoot>,
 }
 
 impl MaterialSymbolsSource {
@@ -174,36 +180,34 @@ impl MaterialSymbolsSource {
         )
     }
 
-    pub fn url(&self) -> anyhow::Result<Url> {
-        Ok(Url::parse(
-            format!(
-                "{}/{}/{}/{}",
-                MATERIAL_SYMBOLS_URL,
-                self.symbol.clone(),
-                self.style.as_dir_name(),
-                self.file_name()
-            )
-            .as_str(),
-        )?)
+    /// Where the symbol is under `symbols/web`.
+    pub fn asset_path(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.symbol.clone(),
+            self.style.as_dir_name(),
+            self.file_name()
+        )
     }
 
-    pub async fn fetch(&self, reqwest: reqwest::Client) -> anyhow::Result<SpriteSource> {
-        let url = self.url()?;
-
-        let svg = reqwest
-            .get(url)
-            .header("cache-control", "public, max-age=3600")
-            .send()
-            .await
-            .context("failed to fetch material symbols")?
-            .text()
+    pub async fn fetch(&self, context: &FetchContext) -> anyhow::Result<SpriteSource> {
+        let svg = context
+            .root(
+                &self.base_url,
+                |sources| &sources.material_symbols,
+                MATERIAL_SYMBOLS_URL,
+            )
+            .fetch_text(context, &self.asset_path())
             .await
-            .context("failed to parse material symbols as text")?
-            .replace(PATH, PATH_REPLACE_WITH_WHITE_FILL);
+            .context("failed to fetch material symbols")?;
 
         let tree = usvg::Tree::from_str(svg.as_str(), &usvg::Options::default())
             .context("failed to parse material symbols as svg")?;
 
-        Ok(SpriteSource::Tree(tree))
+        // Material Symbols ship black, so they start out white to be tinted
+        // in engine like other icons.
+        Ok(SpriteSource::Tree(tree)
+            .recolored(Some(HexColor::WHITE), None)?
+            .into_owned())
     }
 }
@@ -1,12 +1,25 @@
-use anyhow::bail;
+use std::{borrow::Cow, collections::BTreeMap};
+
+use anyhow::{Context, bail};
 use serde::Deserialize;
-use tiny_skia::Pixmap;
-use usvg::Tree;
+use tiny_skia::{Pixmap, PixmapPaint, Transform};
+use usvg::{Options, Tree, WriteOptions};
+
+use crate::{
+    processing::{
+        filter::StateFilter,
+        recolor::{HexColor, recolor_pixmap, recolor_svg},
+    },
+    sources::fetch::FetchContext,
+};
 
+pub mod fetch;
 #[cfg(feature = "source_fluent")]
 pub mod fluent;
 #[cfg(feature = "source_font_awesome")]
 pub mod font_awesome;
+#[cfg(feature = "source_layers")]
+pub mod layers;
 #[cfg(feature = "source_luicide")]
 pub mod luicide;
 #[cfg(feature = "source_material_symbols")]
@@ -23,6 +36,8 @@ pub enum SpriteSpecifier {
     Fluent(self::fluent::FluentSource),
     #[cfg(feature = "source_font_awesome")]
     FontAwesome(self::font_awesome::FontAwesomeSource),
+    #[cfg(feature = "source_layers")]
+    Layers(self::layers::LayersSource),
     #[cfg(feature = "source_luicide")]
     Luicide(self::luicide::LuicideSource),
     #[cfg(feature = "source_material_symbols")]
@@ -31,20 +46,341 @@ pub enum SpriteSpecifier {
     Path(self::path::PathSource),
 }
 
+/// A sprite as written in a spritesheet's `sprites` table: any source, plus
+/// options that apply regardless of where the sprite comes from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct SpriteEntry {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub source: SpriteSpecifier,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub options: SpriteOptions,
+}
+
+/// Options shared by every sprite, whatever its source. They're kept on the
+/// generated `Sprite` so outputs can read them too.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SpriteOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub scale: Option<f32>,
+    /// Overrides the spritesheet's `fit` for this sprite.
+    pub fit: Option<Fit>,
+    /// Overrides the spritesheet's `align` for this sprite.
+    pub align: Option<Align>,
+    /// Extra empty pixels around this sprite on the spritesheet, on top of
+    /// the spritesheet's `padding`.
+    pub padding: u32,
+    /// Turns the sprite clockwise by this many degrees after it's drawn. Must
+    /// be a multiple of 90.
+    pub rotation: u32,
+    /// Replaces every colour of the sprite, keeping its transparency.
+    /// Overrides the spritesheet's `color`.
+    pub color: Option<HexColor>,
+    /// Multiplies every colour of the sprite. Overrides the spritesheet's
+    /// `tint`.
+    pub tint: Option<HexColor>,
+    /// Free-form labels passed through to outputs.
+    pub tags: Vec<String>,
+    /// Extra states of this sprite, such as `disabled` or `hover`, by name.
+    /// Added to and overriding the spritesheet's `states`.
+    pub states: BTreeMap<String, StateFilter>,
+}
+
+impl SpriteOptions {
+    /// The size this sprite should be drawn at, given the intrinsic size of its
+    /// source. Setting only one of `width` or `height` keeps the aspect ratio.
+    /// Returns `None` when the options don't specify a size.
+    pub fn size(&self, (intrinsic_width, intrinsic_height): (f32, f32)) -> Option<(u32, u32)> {
+        let (width, height) = match (self.width, self.height, self.scale) {
+            (Some(width), Some(height), _) => (width as f32, height as f32),
+            (Some(width), None, _) => (
+                width as f32,
+                width as f32 * intrinsic_height / intrinsic_width,
+            ),
+            (None, Some(height), _) => (
+                height as f32 * intrinsic_width / intrinsic_height,
+                height as f32,
+            ),
+            (None, None, Some(scale)) => (intrinsic_width * scale, intrinsic_height * scale),
+            (None, None, None) => return None,
+        };
+
+        Some((
+            (width.round() as u32).max(1),
+            (height.round() as u32).max(1),
+        ))
+    }
+
+    /// How many quarter turns clockwise `rotation` makes, or `None` if it
+    /// isn't a multiple of 90 degrees.
+    pub fn quarter_turns(&self) -> Option<u32> {
+        self.rotation
+            .is_multiple_of(90)
+            .then_some(self.rotation / 90 % 4)
+    }
+}
+
+/// How a source is drawn into a sprite whose aspect ratio doesn't match.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Fit {
+    /// Stretches the source to fill the sprite.
+    #[default]
+    Stretch,
+    /// Scales the source to fit inside the sprite, leaving the rest
+    /// transparent.
+    Contain,
+    /// Scales the source to fill the sprite, cropping whatever doesn't fit.
+    Cover,
+    /// Draws the source at its intrinsic size, cropping whatever doesn't fit.
+    None,
+}
+
+impl Fit {
+    /// How much to scale a source of `intrinsic` size on each axis to draw it
+    /// into `size`.
+    pub fn scale(
+        &self,
+        (intrinsic_width, intrinsic_height): (f32, f32),
+        (width, height): (f32, f32),
+    ) -> (f32, f32) {
+        let (scale_x, scale_y) = (width / intrinsic_width, height / intrinsic_height);
+
+        match self {
+            Self::Stretch => (scale_x, scale_y),
+            Self::Contain => (scale_x.min(scale_y), scale_x.min(scale_y)),
+            Self::Cover => (scale_x.max(scale_y), scale_x.max(scale_y)),
+            Self::None => (1.0, 1.0),
+        }
+    }
+}
+
+/// Where a source sits in its sprite when `fit` leaves space around it or
+/// crops it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Align {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Align {
+    /// How far along the leftover space the source sits on each axis, from `0`
+    /// at the top left to `1` at the bottom right.
+    pub fn factors(&self) -> (f32, f32) {
+        match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum SpriteSource {
     Pixmap(Pixmap),
     #[cfg(feature = "svg")]
     Tree(Tree),
+    /// Other sources drawn on top of each other, from the bottom up.
+    Layers(Vec<SourceLayer>),
+}
+
+/// One source of a `SpriteSource::Layers`.
+#[derive(Debug, Clone)]
+pub struct SourceLayer {
+    pub source: SpriteSource,
+    /// Where the layer is drawn in the intrinsic space of the layers.
+    pub transform: Transform,
+    pub opacity: f32,
+}
+
+impl SpriteSource {
+    /// The intrinsic size of the source, before any scaling.
+    pub fn size(&self) -> (f32, f32) {
+        match self {
+            Self::Pixmap(pixmap) => (pixmap.width() as f32, pixmap.height() as f32),
+            #[cfg(feature = "svg")]
+            Self::Tree(tree) => {
+                let size = tree.size();
+                (size.width(), size.height())
+            }
+            Self::Layers(layers) => layers.iter().fold((0.0, 0.0), |(width, height), layer| {
+                let (layer_width, layer_height) = layer.source.size();
+                (
+                    width.max(layer.transform.tx + layer_width * layer.transform.sx),
+                    height.max(layer.transform.ty + layer_height * layer.transform.sy),
+                )
+            }),
+        }
+    }
+
+    /// Replaces every colour with `color` and then multiplies it by `tint`,
+    /// borrowing the source as is when there's nothing to do.
+    pub fn recolored(
+        &self,
+        color: Option<HexColor>,
+        tint: Option<HexColor>,
+    ) -> anyhow::Result<Cow<'_, Self>> {
+        if color.is_none() && tint.is_none() {
+            return Ok(Cow::Borrowed(self));
+        }
+
+        Ok(Cow::Owned(match self {
+            Self::Pixmap(pixmap) => {
+                let mut pixmap = pixmap.clone();
+                recolor_pixmap(&mut pixmap, color, tint);
+                Self::Pixmap(pixmap)
+            }
+            #[cfg(feature = "svg")]
+            Self::Tree(tree) => {
+                // usvg trees can't be edited, so the tree is written back out
+                // and parsed again with its colours replaced.
+                let svg = recolor_svg(&tree.to_string(&WriteOptions::default()), color, tint);
+                Self::Tree(
+                    Tree::from_str(&svg, &Options::default())
+                        .context("failed to parse recolored svg")?,
+                )
+            }
+            Self::Layers(layers) => Self::Layers(
+                layers
+                    .iter()
+                    .map(|layer| {
+                        Ok(SourceLayer {
+                            source: layer.source.recolored(color, tint)?.into_owned(),
+                            ..*layer
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+        }))
+    }
+
+    /// Rasterizes the source into a new `width` by `height` pixmap, scaled
+    /// according to `fit` and positioned according to `align`.
+    pub fn render(&self, width: u32, height: u32, fit: Fit, align: Align) -> Option<Pixmap> {
+        let mut pixmap = Pixmap::new(width, height)?;
+
+        let (intrinsic_width, intrinsic_height) = self.size();
+        let (scale_x, scale_y) = fit.scale(
+            (intrinsic_width, intrinsic_height),
+            (width as f32, height as f32),
+        );
+        let (align_x, align_y) = align.factors();
+
+        // Whole pixel offsets keep pixel art sources crisp.
+        let transform = Transform::from_scale(scale_x, scale_y).post_translate(
+            ((width as f32 - intrinsic_width * scale_x) * align_x).round(),
+            ((height as f32 - intrinsic_height * scale_y) * align_y).round(),
+        );
+
+        self.draw(&mut pixmap, transform)?;
+
+        Some(pixmap)
+    }
+
+    /// Draws the source onto `pixmap`, with its intrinsic space mapped by
+    /// `transform`.
+    fn draw(&self, pixmap: &mut Pixmap, transform: Transform) -> Option<()> {
+        match self {
+            Self::Pixmap(source) => {
+                pixmap.draw_pixmap(
+                    0,
+                    0,
+                    source.as_ref(),
+                    &PixmapPaint::default(),
+                    transform,
+                    None,
+                );
+            }
+            #[cfg(feature = "svg")]
+            Self::Tree(tree) => {
+                resvg::render(tree, transform, &mut pixmap.as_mut());
+            }
+            Self::Layers(layers) => {
+                for layer in layers {
+                    let transform = layer.transform.post_concat(transform);
+
+                    if layer.opacity >= 1.0 {
+                        layer.source.draw(pixmap, transform)?;
+                        continue;
+                    }
+
+                    // Trees can't be drawn with an opacity, so translucent
+                    // layers are drawn on their own first.
+                    let mut layer_pixmap = Pixmap::new(pixmap.width(), pixmap.height())?;
+                    layer.source.draw(&mut layer_pixmap, transform)?;
+                    pixmap.draw_pixmap(
+                        0,
+                        0,
+                        layer_pixmap.as_ref(),
+                        &PixmapPaint {
+                            opacity: layer.opacity.max(0.0),
+                            ..Default::default()
+                        },
+                        Transform::identity(),
+                        None,
+                    );
+                }
+            }
+        }
+
+        Some(())
+    }
 }
 
 impl SpriteSpecifier {
-    pub async fn fetch(&self, reqwest: reqwest::Client) -> anyhow::Result<SpriteSource> {
+    pub fn source_type(&self) -> String {
+        match self {
+            #[cfg(feature = "source_fluent")]
+            Self::Fluent(_) => "fluent",
+            #[cfg(feature = "source_font_awesome")]
+            Self::FontAwesome(_) => "font_awesome",
+            #[cfg(feature = "source_layers")]
+            Self::Layers(_) => "layers",
+            #[cfg(feature = "source_luicide")]
+            Self::Luicide(_) => "luicide",
+            #[cfg(feature = "source_material_symbols")]
+            Self::MaterialSymbols(_) => "material_symbols",
+            #[cfg(feature = "source_path")]
+            Self::Path(_) => "path",
+            #[allow(unreachable_patterns)]
+            _ => "unknown",
+        }
+        .to_string()
+    }
+
+    pub async fn fetch(&self, context: &FetchContext) -> anyhow::Result<SpriteSource> {
         Ok(match self {
+            #[cfg(feature = "source_fluent")]
+            Self::Fluent(fluent) => fluent.fetch(context).await?,
+            #[cfg(feature = "source_font_awesome")]
+            Self::FontAwesome(font_awesome) => font_awesome.fetch(context).await?,
+            #[cfg(feature = "source_layers")]
+            Self::Layers(layers) => layers.fetch(context).await?,
             #[cfg(feature = "source_luicide")]
-            Self::Luicide(luicide) => luicide.fetch(reqwest.clone()).await?,
+            Self::Luicide(luicide) => luicide.fetch(context).await?,
             #[cfg(feature = "source_material_symbols")]
-            Self::MaterialSymbols(material) => material.fetch(reqwest.clone()).await?,
+            Self::MaterialSymbols(material) => material.fetch(context).await?,
             #[cfg(feature = "source_path")]
             Self::Path(path) => path.fetch().await?,
             #[allow(unreachable_patterns)]
@@ -52,3 +388,110 @@ impl SpriteSpecifier {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::Color;
+
+    fn red_source(width: u32, height: u32) -> SpriteSource {
+        let mut pixmap = Pixmap::new(width, height).unwrap();
+        pixmap.fill(Color::from_rgba8(255, 0, 0, 255));
+        SpriteSource::Pixmap(pixmap)
+    }
+
+    fn opaque_columns(pixmap: &Pixmap) -> Vec<bool> {
+        (0..pixmap.width())
+            .map(|x| pixmap.pixel(x, 0).unwrap().alpha() == 255)
+            .collect()
+    }
+
+    #[test]
+    fn sizes_sprites_from_their_options() {
+        let size = |options: SpriteOptions| options.size((20.0, 10.0));
+
+        assert_eq!(size(SpriteOptions::default()), None);
+        assert_eq!(
+            size(SpriteOptions {
+                width: Some(8),
+                ..Default::default()
+            }),
+            Some((8, 4))
+        );
+        assert_eq!(
+            size(SpriteOptions {
+                height: Some(8),
+                ..Default::default()
+            }),
+            Some((16, 8))
+        );
+        assert_eq!(
+            size(SpriteOptions {
+                width: Some(6),
+                height: Some(6),
+                scale: Some(4.0),
+                ..Default::default()
+            }),
+            Some((6, 6))
+        );
+        assert_eq!(
+            size(SpriteOptions {
+                scale: Some(1.5),
+                ..Default::default()
+            }),
+            Some((30, 15))
+        );
+        assert_eq!(
+            size(SpriteOptions {
+                scale: Some(0.01),
+                ..Default::default()
+            }),
+            Some((1, 1))
+        );
+    }
+
+    #[test]
+    fn contains_and_aligns_sources() {
+        let source = red_source(2, 4);
+
+        let centered = source.render(4, 4, Fit::Contain, Align::Center).unwrap();
+        assert_eq!(opaque_columns(&centered), [false, true, true, false]);
+
+        let left = source.render(4, 4, Fit::Contain, Align::Left).unwrap();
+        assert_eq!(opaque_columns(&left), [true, true, false, false]);
+    }
+
+    #[test]
+    fn covers_and_stretches_sources() {
+        let source = red_source(2, 4);
+
+        for fit in [Fit::Cover, Fit::Stretch] {
+            let pixmap = source.render(4, 4, fit, Align::default()).unwrap();
+            assert!(pixmap.pixels().iter().all(|pixel| pixel.alpha() == 255));
+        }
+
+        let none = source.render(4, 4, Fit::None, Align::Right).unwrap();
+        assert_eq!(opaque_columns(&none), [false, false, true, true]);
+    }
+
+    #[test]
+    fn draws_layers_on_top_of_each_other() {
+        let layers = SpriteSource::Layers(vec![
+            SourceLayer {
+                source: red_source(2, 1),
+                transform: Transform::identity(),
+                opacity: 1.0,
+            },
+            SourceLayer {
+                source: red_source(1, 1),
+                transform: Transform::from_translate(2.0, 0.0),
+                opacity: 0.5,
+            },
+        ]);
+        assert_eq!(layers.size(), (3.0, 1.0));
+
+        let pixmap = layers.render(3, 1, Fit::Stretch, Align::default()).unwrap();
+        let alphas: Vec<u8> = pixmap.pixels().iter().map(|pixel| pixel.alpha()).collect();
+        assert_eq!(alphas, [255, 255, 128]);
+    }
+}